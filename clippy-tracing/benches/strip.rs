@@ -0,0 +1,31 @@
+//! Benchmarks the `strip` action over a large synthetic file.
+//!
+//! Compare implementations with `cargo bench -- --save-baseline before` then
+//! `cargo bench -- --baseline before`.
+
+use clippy_tracing::{apply_str, Action, Options};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+/// The number of functions in the synthetic file.
+const FUNCTIONS: usize = 10_000;
+
+fn synthetic() -> String {
+    (0..FUNCTIONS)
+        .map(|i| {
+            format!(
+                "#[tracing::instrument(level = \"trace\", skip(lhs, rhs))]\nfn add_{i}(lhs: i32, rhs: i32) -> i32 {{\n    lhs + rhs\n}}\n"
+            )
+        })
+        .collect()
+}
+
+fn strip(c: &mut Criterion) {
+    let text = synthetic();
+    let options = Options::default();
+    c.bench_function("strip", |b| {
+        b.iter(|| apply_str(&Action::Strip, &options, black_box(&text)).unwrap());
+    });
+}
+
+criterion_group!(benches, strip);
+criterion_main!(benches);
@@ -0,0 +1,2344 @@
+//! A library to add, remove and check for `tracing::instrument` in large projects where it is infeasible to manually add it to thousands of functions.
+//!
+//! This backs the `clippy-tracing` binary and allows applying actions to sources which are not on
+//! the filesystem e.g. editor buffers.
+
+#![warn(clippy::pedantic, clippy::restriction)]
+#![allow(
+    clippy::blanket_clippy_restriction_lints,
+    clippy::single_call_fn,
+    clippy::absolute_paths,
+    clippy::pattern_type_mismatch,
+    clippy::implicit_return,
+    clippy::question_mark_used,
+    clippy::missing_trait_methods,
+    clippy::min_ident_chars,
+    clippy::wildcard_enum_match_arm,
+    clippy::arithmetic_side_effects,
+    clippy::single_char_lifetime_names,
+    clippy::arbitrary_source_item_ordering,
+    clippy::allow_attributes_without_reason,
+    clippy::missing_inline_in_public_items,
+    clippy::struct_excessive_bools,
+    clippy::exhaustive_enums
+)]
+
+extern crate alloc;
+
+use alloc::fmt;
+use alloc::sync::Arc;
+use core::error::Error;
+use std::io::{Read, Write};
+use syn::spanned::Spanned as _;
+use syn::visit::Visit as _;
+
+/// Decides whether `fix` inserts the given instrument attribute for the function with the given
+/// span and identifier.
+pub type Confirm = Arc<dyn Fn(proc_macro2::Span, &str, &str) -> bool + Send + Sync>;
+
+/// The options used when applying an action.
+#[derive(Clone, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// A custom path suffix.
+    pub suffix: Option<String>,
+    /// A `cfg_attr` condition.
+    pub cfg_attr: Option<String>,
+    /// The default behaviours.
+    pub behavior: Behavior,
+    /// Whether `const fn`s are instrumented.
+    pub include_const: bool,
+    /// Whether trait methods with default implementations are instrumented.
+    pub trait_methods: bool,
+    /// Identifiers of attributes below which instrumentation is inserted.
+    pub place_below: Vec<String>,
+    /// The level instrumentation must have.
+    pub enforce_level: Option<String>,
+    /// Functions with a `#[cfg(...)]` predicate containing any of these strings are skipped.
+    pub skip_cfg: Vec<String>,
+    /// Whether to warn about functions defined within `macro_rules!` bodies.
+    pub warn_macro_rules: bool,
+    /// Whether to warn when instrumenting `#[track_caller]` functions.
+    pub warn_track_caller: bool,
+    /// Whether `check` warns about functions missing instrumentation which have a commented out
+    /// instrument attribute above them.
+    pub warn_commented_instrument: bool,
+    /// Whether `strip` removes top-level imports of `instrument` which become unused.
+    pub clean_imports: bool,
+    /// The level of the instrument attributes removed by `strip`, if any others are kept.
+    pub strip_level: Option<String>,
+    /// The maximum nesting depth of a file, if any.
+    pub max_depth: Option<usize>,
+    /// Whether files which fail to parse are parsed as a sequence of statements, such as
+    /// fragments which are `include!`d into a function body.
+    pub allow_fragments: bool,
+    /// The minimum number of branch points a function must have to require instrumentation.
+    pub min_branches: Option<usize>,
+    /// The minimum number of parameters a function must have to require instrumentation.
+    pub min_args: Option<usize>,
+    /// Whether the receiver e.g. `&self` counts towards `min_args`.
+    pub min_args_count_self: bool,
+    /// The level free functions named `main` at the root of a file are instrumented with.
+    pub main_level: Option<String>,
+    /// The level other functions are instrumented with, `trace` if unset.
+    pub level: Option<String>,
+    /// Identifiers of parameter types which are recorded rather than skipped.
+    pub record_types: Vec<String>,
+    /// Whether `self` is recorded rather than skipped, which requires `Self: Debug`.
+    pub record_receiver: bool,
+    /// Identifiers of impl methods which are skipped.
+    pub skip_methods: Vec<String>,
+    /// Whether functions with a `#[deprecated]` attribute are skipped.
+    pub skip_deprecated: bool,
+    /// The ranges of changed lines, if any, only functions overlapping a range require
+    /// instrumentation.
+    pub changed_lines: Option<Vec<core::ops::Range<usize>>>,
+    /// Whether instrumentation records the return value of functions which return one.
+    pub ret: bool,
+    /// Whether inserted instrumentation is followed by a comment marking it as generated.
+    pub tag_comment: bool,
+    /// The minimum number of blank lines between inserted instrumentation and the preceding item
+    /// or comment, blank lines are only added.
+    pub gap_lines: usize,
+    /// Identifiers of attributes which apply instrumentation themselves so are treated as
+    /// instrumentation.
+    pub treats_as_instrumented: Vec<String>,
+    /// Whether only attributes with the full path of the instrument attribute e.g.
+    /// `tracing::instrument` are treated as instrumentation, rather than any ending in
+    /// `instrument`.
+    pub strict_instrument_path: bool,
+    /// Whether functions whose body is a single trivial expression are skipped.
+    pub skip_trivial: bool,
+    /// The line endings of modified sources.
+    pub newline_style: NewlineStyle,
+    /// Whether `check` requires instrumentation to have `skip(..)` or `skip_all`.
+    pub require_skip: bool,
+    /// Identifiers of the functions which require instrumentation, if empty all functions do.
+    pub only_fns: Vec<String>,
+    /// Identifiers of the functions which start new traces so are instrumented with
+    /// `parent = None`.
+    pub root_fns: Vec<String>,
+    /// The maximum number of functions `fix` instruments, later functions are left
+    /// uninstrumented.
+    pub limit: Option<usize>,
+    /// Decides whether `fix` inserts each instrument attribute, if not all are inserted.
+    pub confirm: Option<Confirm>,
+    /// Whether `fix` regenerates existing instrumentation in place, keeping its level.
+    pub refresh: bool,
+    /// Whether instrumentation records the errors of functions returning a `Result`.
+    pub with_err: bool,
+    /// Whether non-empty `skip(..)` lists of inserted instrumentation end with a trailing comma.
+    pub trailing_comma: bool,
+    /// Identifiers of type aliases of `Result` e.g. `Fallible`.
+    pub result_aliases: Vec<String>,
+    /// Whether functions with an explicit ABI e.g. `extern "C" fn` are skipped.
+    pub skip_extern_abi: bool,
+    /// Whether functions with an entry point attribute macro e.g. `#[tokio::main]` are skipped.
+    pub skip_entry_macros: bool,
+    /// Whether functions with a `#[cfg(test)]` attribute are skipped.
+    pub skip_cfg_test: bool,
+    /// The enabled features, if given functions within modules with a `#[cfg(feature = "..")]`
+    /// predicate for any other feature are not instrumented.
+    pub active_features: Option<Vec<String>>,
+    /// Whether only free functions require instrumentation, not impl or trait methods.
+    pub only_free_fns: bool,
+    /// Whether only impl and trait methods require instrumentation, not free functions.
+    pub only_methods: bool,
+    /// Whether `check` collects the parameter types of the functions it checks.
+    pub collect_arg_types: bool,
+    /// Whether `check` reports the functions which are intentionally not instrumented, with the
+    /// reason.
+    pub report_skipped: bool,
+    /// Whether `check` explains why each function missing instrumentation requires it.
+    pub explain: bool,
+    /// Whether instrumentation sets the OpenTelemetry span name `otel.name` to the path of the
+    /// function within the file e.g. `module::Type::method`.
+    pub otel_name: bool,
+    /// Whether `fix` with an empty `suffix` inserts an import of `instrument` into files which
+    /// gained instrumentation and do not import it.
+    pub ensure_import: bool,
+}
+
+/// A release version of this tool.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
+pub struct Version {
+    /// The major version.
+    pub major: u64,
+    /// The minor version.
+    pub minor: u64,
+    /// The patch version.
+    pub patch: u64,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Error for parsing a [`Version`].
+#[derive(Debug)]
+pub enum VersionError {
+    /// Failed to parse a component of the version to an integer.
+    Component(core::num::ParseIntError),
+    /// The version has more than 3 components.
+    Length,
+}
+impl fmt::Display for VersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Component(component) => {
+                write!(f, "Failed to parse version component: {component}")
+            }
+            Self::Length => write!(f, "Version has more than 3 components."),
+        }
+    }
+}
+
+impl Error for VersionError {}
+
+impl Version {
+    /// Returns the version of this release.
+    #[must_use]
+    pub fn current() -> Self {
+        let component = |component: &str| component.parse().unwrap_or_default();
+        Self {
+            major: component(env!("CARGO_PKG_VERSION_MAJOR")),
+            minor: component(env!("CARGO_PKG_VERSION_MINOR")),
+            patch: component(env!("CARGO_PKG_VERSION_PATCH")),
+        }
+    }
+}
+
+impl core::str::FromStr for Version {
+    type Err = VersionError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut components = [0; 3];
+        for (i, component) in s.split('.').enumerate() {
+            let c = components.get_mut(i).ok_or(VersionError::Length)?;
+            *c = component.parse().map_err(VersionError::Component)?;
+        }
+        let [major, minor, patch] = components;
+        Ok(Self {
+            major,
+            minor,
+            patch,
+        })
+    }
+}
+
+/// The default behaviours of the tool which changed between releases.
+///
+/// These are seeded by the `--compat` version then overridden by explicit flags, this allows
+/// upgrading the tool without immediately changing its output.
+#[derive(Clone, Copy)]
+#[non_exhaustive]
+pub struct Behavior {
+    /// Whether `target` and `.git` directories are skipped when walking (since 0.8.0).
+    pub skip_target: bool,
+    /// Whether the messages of `check` name the function e.g. ``Missing instrumentation for `add`
+    /// at ..`` rather than `Missing instrumentation at ..` (since 0.8.0).
+    pub named_messages: bool,
+    /// Whether `check` checks the functions nested within functions missing instrumentation
+    /// (since 0.8.0).
+    pub check_nested: bool,
+}
+impl Default for Behavior {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+impl Behavior {
+    /// Returns the default behaviours of the given release, `None` returns the default behaviours
+    /// of this release.
+    #[must_use]
+    pub fn new(compat: Option<Version>) -> Self {
+        let version = compat.unwrap_or_else(Version::current);
+        let since = |major, minor, patch| {
+            version
+                >= Version {
+                    major,
+                    minor,
+                    patch,
+                }
+        };
+        Self {
+            skip_target: since(0, 8, 0),
+            named_messages: since(0, 8, 0),
+            check_nested: since(0, 8, 0),
+        }
+    }
+}
+
+/// The action to take.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Checks `tracing::instrument` is on all functions.
+    Check,
+    /// Adds `tracing::instrument` to all functions.
+    Fix,
+    /// Removes `tracing::instrument` from all functions.
+    Strip,
+    /// Removes all but the first `tracing::instrument` from functions with multiple.
+    Dedupe,
+}
+
+/// The line endings of modified sources.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum NewlineStyle {
+    /// Keeps the line ending of each original line, inserted lines use the line ending of the
+    /// line after them.
+    #[default]
+    Auto,
+    /// Uses `\n`.
+    Lf,
+    /// Uses `\r\n`.
+    Crlf,
+}
+impl NewlineStyle {
+    /// Returns the given text with this line ending style.
+    fn apply(self, text: String) -> String {
+        match self {
+            Self::Auto => text,
+            Self::Lf => newlines(&text, false),
+            Self::Crlf => newlines(&text, true),
+        }
+    }
+}
+
+/// Returns the line ending of the given line of source lines split on `\n`, the last line has no
+/// line ending so that of the line before it is used.
+fn line_ending<S: AsRef<str>>(lines: &[S], line: usize) -> &'static str {
+    let last = lines.len().saturating_sub(1);
+    let index = line.min(last.saturating_sub(1));
+    if index < last && lines.get(index).is_some_and(|x| x.as_ref().ends_with('\r')) {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+/// Returns the given inserted lines with the given line ending, including the line ending after
+/// the last line.
+fn with_ending(text: &str, ending: &str) -> String {
+    let mut lines = text.replace('\n', ending);
+    if ending == "\r\n" {
+        lines.push('\r');
+    }
+    lines
+}
+
+/// Returns the given text with all line endings replaced with `\r\n` if `crlf`, else `\n`.
+fn newlines(text: &str, crlf: bool) -> String {
+    let lf = text.replace("\r\n", "\n");
+    if crlf {
+        lf.replace('\n', "\r\n")
+    } else {
+        lf
+    }
+}
+
+/// A list of text lines split so that newlines can be efficiently inserted between them.
+struct SegmentedList {
+    /// The first new line.
+    first: String,
+    /// The inner vector used to contain the original lines `.0` and the new lines `.1`.
+    inner: Vec<(String, String)>,
+    /// The edits of the original lines `.0` from the columns `.1`.
+    edits: Vec<(usize, usize, Edit)>,
+}
+
+/// An edit of an original line of a [`SegmentedList`].
+enum Edit {
+    /// Splits the line such that the text `.1` is on its own line between, after `.0` blank lines.
+    Split(usize, String),
+    /// Replaces the text up to the original line `.0` and column `.1` with `.2`.
+    Replace(usize, usize, String),
+}
+impl SegmentedList {
+    /// Sets the text line before `line` to `text`.
+    fn set_before(&mut self, line: usize, text: String) -> bool {
+        let s = if let Some(i) = line.checked_sub(1) {
+            let Some(mut_ref) = self.inner.get_mut(i) else {
+                return false;
+            };
+            &mut mut_ref.1
+        } else {
+            &mut self.first
+        };
+        *s = text;
+        true
+    }
+    /// Returns whether the original `line` has non-whitespace text before `column`.
+    fn is_mid_line(&self, line: usize, column: usize) -> bool {
+        self.inner
+            .get(line)
+            .is_some_and(|(original, _)| original.chars().take(column).any(|c| !c.is_whitespace()))
+    }
+    /// Returns the number of blank lines to insert before the original `line` such that at least
+    /// `gap` blank lines separate it from the preceding line, which is none when it is the first
+    /// line in the file or block.
+    fn missing_gap(&self, line: usize, gap: usize) -> usize {
+        let mut preceding = self.inner.iter().take(line).rev();
+        let blank = preceding
+            .clone()
+            .take_while(|(original, _)| original.trim().is_empty())
+            .count();
+        match preceding.nth(blank) {
+            Some((original, _)) if !original.trim_end().ends_with('{') => gap.saturating_sub(blank),
+            _ => 0,
+        }
+    }
+    /// Splits the original `line` before `column` such that `text` is on its own line between,
+    /// separated from the preceding text by `gap` blank lines unless it opens a block.
+    fn split_before(&mut self, line: usize, column: usize, gap: usize, text: String) {
+        let opens = self.inner.get(line).is_some_and(|(original, _)| {
+            original
+                .chars()
+                .take(column)
+                .collect::<String>()
+                .trim_end()
+                .ends_with('{')
+        });
+        self.edits
+            .push((line, column, Edit::Split(if opens { 0 } else { gap }, text)));
+    }
+    /// Returns the original text from `start` to `end`.
+    fn original(&self, start: proc_macro2::LineColumn, end: proc_macro2::LineColumn) -> String {
+        let lines = self
+            .inner
+            .iter()
+            .enumerate()
+            .take(end.line)
+            .skip(start.line - 1)
+            .map(|(index, (original, _))| {
+                let from = if index == start.line - 1 {
+                    start.column
+                } else {
+                    0
+                };
+                let to = if index == end.line - 1 {
+                    end.column
+                } else {
+                    usize::MAX
+                };
+                original.chars().take(to).skip(from).collect::<String>()
+            });
+        itertools::join(lines, "\n")
+    }
+    /// Replaces the original text from `start` to `end` with `text`.
+    fn replace(
+        &mut self,
+        start: proc_macro2::LineColumn,
+        end: proc_macro2::LineColumn,
+        text: String,
+    ) {
+        self.edits.push((
+            start.line - 1,
+            start.column,
+            Edit::Replace(end.line - 1, end.column, text),
+        ));
+    }
+}
+impl From<SegmentedList> for String {
+    fn from(mut list: SegmentedList) -> String {
+        // Inserted lines use the line ending of the original line after them.
+        let endings = {
+            let lines = list.inner.iter().map(|(x, _)| x).collect::<Vec<_>>();
+            (0..=lines.len())
+                .map(|line| line_ending(&lines, line))
+                .collect::<Vec<_>>()
+        };
+        let ending = |line: usize| endings.get(line).copied().unwrap_or("\n");
+        if !list.first.is_empty() {
+            list.first = with_ending(&list.first, ending(0));
+        }
+        for (line, (_, new)) in list.inner.iter_mut().enumerate() {
+            if !new.is_empty() {
+                *new = with_ending(new, ending(line + 1));
+            }
+        }
+        // Edit from the last column so the earlier columns and lines remain valid.
+        list.edits
+            .sort_by(|(a_line, a_column, _), (b_line, b_column, _)| {
+                (b_line, b_column).cmp(&(a_line, a_column))
+            });
+        for (line, column, edit) in list.edits {
+            match edit {
+                Edit::Split(gap, text) => {
+                    if let Some((original, _)) = list.inner.get_mut(line) {
+                        let offset = original
+                            .char_indices()
+                            .nth(column)
+                            .map_or(original.len(), |(offset, _)| offset);
+                        let (before, after) = original.split_at(offset);
+                        let indent = before
+                            .chars()
+                            .take_while(|c| c.is_whitespace())
+                            .collect::<String>();
+                        let end = ending(line);
+                        *original = format!(
+                            "{}{end}{}{indent}{text}{end}{indent}{after}",
+                            before.trim_end(),
+                            end.repeat(gap)
+                        );
+                    }
+                }
+                Edit::Replace(end_line, end_column, text) => {
+                    let Some((end_original, _)) = list.inner.get(end_line) else {
+                        continue;
+                    };
+                    let after = end_original.chars().skip(end_column).collect::<String>();
+                    list.inner.drain(line + 1..=end_line);
+                    if let Some((original, _)) = list.inner.get_mut(line) {
+                        let before = original.chars().take(column).collect::<String>();
+                        *original = format!("{before}{text}{after}");
+                    }
+                }
+            }
+        }
+        let iter = list
+            .inner
+            .into_iter()
+            .map(|(x, y)| format!("{x}{}{y}", if y.is_empty() { "" } else { "\n" }));
+        format!(
+            "{}{}{}",
+            list.first,
+            if list.first.is_empty() { "" } else { "\n" },
+            itertools::intersperse(iter, String::from("\n")).collect::<String>()
+        )
+    }
+}
+
+/// Visitor for the `strip` action.
+struct StripVisitor<'a> {
+    /// The source lines indexed by line, where removed lines are set to `None`.
+    lines: Vec<Option<&'a str>>,
+    /// The number of attributes removed.
+    removed: usize,
+    /// The level of the attributes removed, if any others are kept.
+    level: Option<&'a str>,
+    /// The options used to find the instrument attributes.
+    options: &'a Options,
+}
+impl<'a> StripVisitor<'a> {
+    /// Returns a visitor for the given source removing attributes of the given level, if any.
+    fn new(text: &'a str, level: Option<&'a str>, options: &'a Options) -> Self {
+        Self {
+            lines: text.split('\n').map(Some).collect(),
+            removed: 0,
+            level,
+            options,
+        }
+    }
+    /// Removes the instrument attribute from the given function attributes, if it has the
+    /// `level`.
+    fn strip(&mut self, attrs: &[syn::Attribute]) {
+        if let Some(instrument) = find_instrumented(attrs, self.options) {
+            if self
+                .level
+                .is_none_or(|level| instrument_level(instrument).eq_ignore_ascii_case(level))
+            {
+                self.remove_attr(instrument);
+            }
+        }
+    }
+    /// Removes the lines covered by the given span.
+    fn remove(&mut self, span: proc_macro2::Span) {
+        let start = span.start().line - 1;
+        let end = span.end().line;
+        for line in self.lines.iter_mut().take(end).skip(start) {
+            *line = None;
+        }
+    }
+    /// Removes the given attribute.
+    fn remove_attr(&mut self, attr: &syn::Attribute) {
+        self.remove(attr.span());
+        self.removed += 1;
+    }
+    /// Removes private top-level imports of `instrument` where the imported name is no longer
+    /// used in the remaining source.
+    fn clean_imports(&mut self, items: &[syn::Item]) {
+        for item in items {
+            let syn::Item::Use(item_use) = item else {
+                continue;
+            };
+            if !matches!(item_use.vis, syn::Visibility::Inherited) {
+                continue;
+            }
+            let Some(name) = instrument_import(&item_use.tree) else {
+                continue;
+            };
+            let start = item_use.span().start().line - 1;
+            let end = item_use.span().end().line;
+            let used = self
+                .lines
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| *index < start || *index >= end)
+                .filter_map(|(_, line)| *line)
+                .any(|line| {
+                    line.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                        .any(|word| word == name)
+                });
+            if !used {
+                self.remove(item_use.span());
+            }
+        }
+    }
+}
+impl From<StripVisitor<'_>> for String {
+    fn from(visitor: StripVisitor) -> String {
+        itertools::intersperse(visitor.lines.into_iter().flatten(), "\n").collect::<String>()
+    }
+}
+impl syn::visit::Visit<'_> for StripVisitor<'_> {
+    fn visit_impl_item_fn(&mut self, i: &syn::ImplItemFn) {
+        self.strip(&i.attrs);
+        self.visit_block(&i.block);
+    }
+    fn visit_item_fn(&mut self, i: &syn::ItemFn) {
+        self.strip(&i.attrs);
+        self.visit_block(&i.block);
+    }
+    fn visit_trait_item_fn(&mut self, i: &syn::TraitItemFn) {
+        self.strip(&i.attrs);
+        if let Some(block) = &i.default {
+            self.visit_block(block);
+        }
+    }
+}
+
+/// The comment marking a function as deliberately uninstrumented for the `check` action.
+const EXPECT_MISSING: &str = "// clippy-tracing:expect-missing";
+
+/// The comment following inserted instrumentation with `tag_comment`.
+const TAG_COMMENT: &str = "// clippy-tracing generated";
+
+/// The import inserted with `ensure_import`.
+#[cfg(not(feature = "log"))]
+const INSTRUMENT_IMPORT: &str = "use tracing::instrument;";
+/// The import inserted with `ensure_import`.
+#[cfg(feature = "log")]
+const INSTRUMENT_IMPORT: &str = "use log_instrument::instrument;";
+
+/// Visitor for the `dedupe` action.
+struct DedupeVisitor<'a>(StripVisitor<'a>);
+impl syn::visit::Visit<'_> for DedupeVisitor<'_> {
+    fn visit_impl_item_fn(&mut self, i: &syn::ImplItemFn) {
+        for instrument in instrument_attrs(&i.attrs, self.0.options).skip(1) {
+            self.0.remove_attr(instrument);
+        }
+        self.visit_block(&i.block);
+    }
+    fn visit_item_fn(&mut self, i: &syn::ItemFn) {
+        for instrument in instrument_attrs(&i.attrs, self.0.options).skip(1) {
+            self.0.remove_attr(instrument);
+        }
+        self.visit_block(&i.block);
+    }
+    fn visit_trait_item_fn(&mut self, i: &syn::TraitItemFn) {
+        for instrument in instrument_attrs(&i.attrs, self.0.options).skip(1) {
+            self.0.remove_attr(instrument);
+        }
+        if let Some(block) = &i.default {
+            self.visit_block(block);
+        }
+    }
+}
+
+/// Visitor for the `check` action.
+struct CheckVisitor<'a> {
+    /// The options.
+    options: &'a Options,
+    /// Source lines.
+    lines: Vec<&'a str>,
+    /// The spans, identifiers and problems of the functions with problems.
+    problems: Vec<(proc_macro2::Span, String, Problem)>,
+    /// The number of functions checked.
+    functions: usize,
+    /// The number of functions checked which are instrumented.
+    covered: usize,
+    /// The spans and reasons of the functions intentionally not instrumented.
+    skipped: Vec<(proc_macro2::Span, &'static str)>,
+    /// The spans and explanations of the functions missing instrumentation.
+    explanations: Vec<(proc_macro2::Span, String)>,
+    /// The spans and enclosing scopes of the functions with problems.
+    scopes: Vec<(proc_macro2::Span, String)>,
+    /// The modules, impls, traits and functions enclosing the item being visited.
+    scope: Vec<String>,
+    /// The identifiers of the path parameter types of the functions checked.
+    arg_types: Vec<String>,
+    /// The spans and messages of warnings.
+    warnings: Vec<(proc_macro2::Span, String)>,
+    /// Whether the item being visited is within a module for a feature which is not active.
+    inactive: bool,
+}
+impl CheckVisitor<'_> {
+    /// Checks the function with the given span, attributes, signature and body, recording the
+    /// enclosing scope of the function if it has problems. Returns whether the functions nested
+    /// within it are checked.
+    fn check(
+        &mut self,
+        span: proc_macro2::Span,
+        attrs: &[syn::Attribute],
+        sig: &syn::Signature,
+        block: &syn::Block,
+    ) -> bool {
+        let problems = self.problems.len();
+        self.check_problems(span, attrs, sig, block);
+        let found = self.problems.get(problems..).unwrap_or_default();
+        let missing = found
+            .iter()
+            .any(|(_, _, problem)| matches!(problem, Problem::Missing));
+        if !found.is_empty() {
+            self.scopes.push((span, itertools::join(&self.scope, "::")));
+        }
+        self.options.behavior.check_nested || !missing
+    }
+    /// Checks the functions nested within the given function body with the given identifier.
+    fn visit_nested(&mut self, ident: &syn::Ident, block: &syn::Block) {
+        self.scope.push(ident.to_string());
+        self.visit_block(block);
+        self.scope.pop();
+    }
+    /// Checks the function with the given span, attributes, signature and body, see
+    /// [`CheckVisitor::check`].
+    fn check_problems(
+        &mut self,
+        span: proc_macro2::Span,
+        attrs: &[syn::Attribute],
+        sig: &syn::Signature,
+        block: &syn::Block,
+    ) {
+        self.functions += 1;
+        if self.options.collect_arg_types {
+            self.arg_types
+                .extend(sig.inputs.iter().filter_map(|arg| match arg {
+                    syn::FnArg::Typed(syn::PatType { ty, .. }) => {
+                        type_ident(ty).map(ToString::to_string)
+                    }
+                    syn::FnArg::Receiver(_) => None,
+                }));
+        }
+        if !self.inactive && requires_instrument(attrs, sig, block, self.options) {
+            if !self.expect_missing(span) {
+                if self.options.warn_commented_instrument && self.commented_instrument(span) {
+                    self.warnings.push((
+                        span,
+                        format!("Instrumentation for `{}` is commented out", sig.ident),
+                    ));
+                }
+                if self.options.explain {
+                    let desc = check_attributes(attrs, self.options);
+                    self.explanations
+                        .push((span, explanation(&desc, sig, self.options)));
+                }
+                self.problems
+                    .push((span, sig.ident.to_string(), Problem::Missing));
+            }
+            return;
+        }
+        let desc = check_attributes(attrs, self.options);
+        if desc.instrumented {
+            self.covered += 1;
+        }
+        if self.options.report_skipped {
+            let reason = if self.inactive {
+                Some("inactive feature")
+            } else {
+                skip_reason(&desc, attrs, sig, self.options)
+            };
+            if let (false, Some(found)) = (desc.instrumented, reason) {
+                self.skipped.push((span, found));
+            }
+        }
+        if let (Some(expected), Some(attr)) = (
+            &self.options.enforce_level,
+            find_instrumented(attrs, self.options),
+        ) {
+            let found = instrument_level(attr);
+            if !found.eq_ignore_ascii_case(expected) {
+                let problem = Problem::Level {
+                    found,
+                    expected: expected.clone(),
+                };
+                self.problems.push((span, sig.ident.to_string(), problem));
+            }
+        }
+        if let (true, Some(attr)) = (
+            self.options.require_skip,
+            find_instrumented(attrs, self.options),
+        ) {
+            if !instrument_meta(attr).is_some_and(|meta| instrument_skips(&meta)) {
+                self.problems
+                    .push((span, sig.ident.to_string(), Problem::MissingSkip));
+            }
+        }
+    }
+    /// Returns whether the line above the function with the given span has the
+    /// `// clippy-tracing:expect-missing` marker.
+    fn expect_missing(&self, span: proc_macro2::Span) -> bool {
+        span.start()
+            .line
+            .checked_sub(2)
+            .and_then(|index| self.lines.get(index))
+            .is_some_and(|line| line.trim_start().starts_with(EXPECT_MISSING))
+    }
+    /// Returns whether the comments and attributes directly above the function with the given
+    /// span include a commented out instrument attribute e.g. `// #[tracing::instrument]`.
+    fn commented_instrument(&self, span: proc_macro2::Span) -> bool {
+        self.lines
+            .iter()
+            .take(span.start().line.saturating_sub(1))
+            .rev()
+            .map(|line| line.trim_start())
+            .take_while(|line| line.starts_with("//") || line.starts_with("#["))
+            .filter_map(|line| line.strip_prefix("//"))
+            .any(|comment| {
+                let code = comment.trim_start_matches('/').trim_start();
+                code.starts_with("#[") && code.contains("instrument")
+            })
+    }
+}
+impl syn::visit::Visit<'_> for CheckVisitor<'_> {
+    // Functions within const contexts are not instrumented as they may be evaluated at compile
+    // time.
+    fn visit_item_const(&mut self, _i: &syn::ItemConst) {}
+    fn visit_impl_item_const(&mut self, _i: &syn::ImplItemConst) {}
+    fn visit_trait_item_const(&mut self, _i: &syn::TraitItemConst) {}
+    fn visit_expr_const(&mut self, _i: &syn::ExprConst) {}
+    fn visit_item_mod(&mut self, i: &syn::ItemMod) {
+        let inactive = self.inactive;
+        self.inactive |= inactive_feature(&i.attrs, self.options);
+        self.scope.push(i.ident.to_string());
+        syn::visit::visit_item_mod(self, i);
+        self.scope.pop();
+        self.inactive = inactive;
+    }
+    fn visit_item_impl(&mut self, i: &syn::ItemImpl) {
+        self.scope.push(impl_scope(i));
+        syn::visit::visit_item_impl(self, i);
+        self.scope.pop();
+    }
+    fn visit_item_trait(&mut self, i: &syn::ItemTrait) {
+        self.scope.push(i.ident.to_string());
+        syn::visit::visit_item_trait(self, i);
+        self.scope.pop();
+    }
+    fn visit_impl_item_fn(&mut self, i: &syn::ImplItemFn) {
+        if skip_method(&i.sig, self.options) || self.check(i.span(), &i.attrs, &i.sig, &i.block) {
+            self.visit_nested(&i.sig.ident, &i.block);
+        }
+    }
+    fn visit_item_fn(&mut self, i: &syn::ItemFn) {
+        if self.options.only_methods || self.check(i.span(), &i.attrs, &i.sig, &i.block) {
+            self.visit_nested(&i.sig.ident, &i.block);
+        }
+    }
+    fn visit_trait_item_fn(&mut self, i: &syn::TraitItemFn) {
+        if let Some(block) = &i.default {
+            if !self.options.trait_methods
+                || self.options.only_free_fns
+                || self.check(i.span(), &i.attrs, &i.sig, block)
+            {
+                self.visit_nested(&i.sig.ident, block);
+            }
+        }
+    }
+}
+
+/// Returns the scope of the items within the given impl, the last segment of its type e.g. `Unit`
+/// or of its type and trait for trait impls e.g. `<Unit as Display>`.
+fn impl_scope(i: &syn::ItemImpl) -> String {
+    let last = |path: &syn::Path| {
+        path.segments
+            .last()
+            .map_or_else(String::new, |segment| segment.ident.to_string())
+    };
+    let ty = match &*i.self_ty {
+        syn::Type::Path(syn::TypePath { path, .. }) => last(path),
+        ty => quote::ToTokens::to_token_stream(ty).to_string(),
+    };
+    match &i.trait_ {
+        Some((_, path, _)) => format!("<{ty} as {}>", last(path)),
+        None => ty,
+    }
+}
+
+/// Visitor for the `fix` action.
+struct FixVisitor<'a> {
+    /// The options.
+    options: &'a Options,
+    /// Source.
+    list: SegmentedList,
+    /// The spans and messages of warnings.
+    warnings: Vec<(proc_macro2::Span, String)>,
+    /// Whether the item being visited is a free function named `main` at the root of the file.
+    main: bool,
+    /// The number of functions instrumented.
+    inserted: usize,
+    /// The modules, impls, traits and functions enclosing the item being visited, as in the
+    /// baseline keys of `check`.
+    scopes: Vec<String>,
+    /// Whether the item being visited is within a module for a feature which is not active.
+    inactive: bool,
+}
+impl FixVisitor<'_> {
+    /// Inserts the instrument attribute for the function with the given span, attributes and
+    /// signature, unless within a module for an inactive feature, after the `limit` or declined by
+    /// `confirm`.
+    fn insert(
+        &mut self,
+        span: proc_macro2::Span,
+        attrs: &[syn::Attribute],
+        sig: &syn::Signature,
+        main: bool,
+    ) {
+        if self.inactive
+            || self
+                .options
+                .limit
+                .is_some_and(|limit| self.inserted >= limit)
+        {
+            return;
+        }
+        let level = match &self.options.main_level {
+            Some(main_level) if main => main_level.as_str(),
+            _ => self.options.level.as_deref().unwrap_or("trace"),
+        };
+        let mut attr_string = instrument(sig, level, self.options, self.otel_name(sig).as_deref());
+        if let Some(confirm) = &self.options.confirm {
+            if !confirm(span, &sig.ident.to_string(), &attr_string) {
+                return;
+            }
+        }
+        if sig.constness.is_some() {
+            self.warnings.push((
+                span,
+                String::from("Instrumenting `const fn`, this may fail to compile"),
+            ));
+        }
+        if cfg!(not(feature = "log")) && self.options.record_receiver && sig.receiver().is_some() {
+            self.warnings.push((
+                span,
+                format!(
+                    "Recording `self` of `{}`, this requires `Self: Debug`",
+                    sig.ident
+                ),
+            ));
+        }
+        if self.options.warn_track_caller
+            && attrs
+                .iter()
+                .any(|attr| attr.path().is_ident("track_caller"))
+        {
+            self.warnings.push((
+                span,
+                String::from(
+                    "Instrumenting `#[track_caller]` function, this may change `Location::caller()`",
+                ),
+            ));
+        }
+        // Insert below the last `--place-below` attribute which ends before the signature, else at
+        // the top.
+        let line = attrs
+            .iter()
+            .filter(|attr| {
+                matches!(attr.path().segments.last(), Some(syn::PathSegment { ident, .. }) if self.options.place_below.iter().any(|below| ident == below))
+            })
+            .map(|attr| attr.span().end().line + 1)
+            .rfind(|line| *line <= sig.span().start().line)
+            .unwrap_or(span.start().line);
+        self.inserted += 1;
+        // As attributes are removed by line `strip` removes the comment with them.
+        if self.options.tag_comment {
+            attr_string = format!("{attr_string} {TAG_COMMENT}");
+        }
+        // Functions preceded by other code on their line e.g. `impl Unit { fn one() {} }` are
+        // split onto a new line so the attribute is not applied to the preceding code.
+        if line == span.start().line && self.list.is_mid_line(line - 1, span.start().column) {
+            self.list.split_before(
+                line - 1,
+                span.start().column,
+                self.options.gap_lines,
+                attr_string,
+            );
+            return;
+        }
+        let indent = span.start().column;
+        let gap = self.list.missing_gap(line - 1, self.options.gap_lines);
+        let indent_attr = format!("{}{}{attr_string}", "\n".repeat(gap), " ".repeat(indent));
+        self.list.set_before(line - 1, indent_attr);
+    }
+    /// Regenerates the `skip(..)` of the first instrument attribute of the function with the given
+    /// attributes and signature in place with `refresh`, keeping its other arguments as written.
+    fn refresh(&mut self, attrs: &[syn::Attribute], sig: &syn::Signature) {
+        if !self.options.refresh {
+            return;
+        }
+        let Some(attr) = find_instrumented(attrs, self.options) else {
+            return;
+        };
+        // Instrumentation within `#[cfg_attr(..)]` is left unchanged.
+        if attr.path().is_ident("cfg_attr") {
+            return;
+        }
+        let mut args = instrument_args(&attr.meta)
+            .into_iter()
+            .map(|(key, start, end)| (key, self.list.original(start, end)))
+            .collect::<Vec<_>>();
+        refresh_skip(&mut args, sig, self.options);
+        let attr_path = attr.path();
+        let path = format!(
+            "{}{}",
+            if attr_path.leading_colon.is_some() {
+                "::"
+            } else {
+                ""
+            },
+            itertools::join(
+                attr_path.segments.iter().map(|segment| &segment.ident),
+                "::"
+            )
+        );
+        let text = if args.is_empty() {
+            format!("#[{path}]")
+        } else {
+            format!(
+                "#[{path}({})]",
+                itertools::join(args.iter().map(|(_, text)| text), ", ")
+            )
+        };
+        let span = attr.span();
+        self.list.replace(span.start(), span.end(), text);
+    }
+    /// Returns the `otel.name` of the function with the given signature with `otel_name`.
+    fn otel_name(&self, sig: &syn::Signature) -> Option<String> {
+        self.options.otel_name.then(|| {
+            let ident = sig.ident.to_string();
+            itertools::join(self.scopes.iter().chain(core::iter::once(&ident)), "::")
+        })
+    }
+}
+impl From<FixVisitor<'_>> for String {
+    fn from(visitor: FixVisitor) -> String {
+        String::from(visitor.list)
+    }
+}
+
+impl syn::visit::Visit<'_> for FixVisitor<'_> {
+    // See `CheckVisitor`.
+    fn visit_item_const(&mut self, _i: &syn::ItemConst) {}
+    fn visit_impl_item_const(&mut self, _i: &syn::ImplItemConst) {}
+    fn visit_trait_item_const(&mut self, _i: &syn::TraitItemConst) {}
+    fn visit_expr_const(&mut self, _i: &syn::ExprConst) {}
+    fn visit_impl_item_fn(&mut self, i: &syn::ImplItemFn) {
+        if !skip_method(&i.sig, self.options)
+            && requires_instrument(&i.attrs, &i.sig, &i.block, self.options)
+        {
+            self.insert(i.span(), &i.attrs, &i.sig, false);
+        } else {
+            self.refresh(&i.attrs, &i.sig);
+        }
+        self.scopes.push(i.sig.ident.to_string());
+        self.visit_block(&i.block);
+        self.scopes.pop();
+    }
+    fn visit_file(&mut self, i: &syn::File) {
+        for item in &i.items {
+            self.main = matches!(item, syn::Item::Fn(item_fn) if item_fn.sig.ident == "main");
+            self.visit_item(item);
+        }
+    }
+    fn visit_item_mod(&mut self, i: &syn::ItemMod) {
+        let inactive = self.inactive;
+        self.inactive |= inactive_feature(&i.attrs, self.options);
+        self.scopes.push(i.ident.to_string());
+        syn::visit::visit_item_mod(self, i);
+        self.scopes.pop();
+        self.inactive = inactive;
+    }
+    fn visit_item_impl(&mut self, i: &syn::ItemImpl) {
+        self.scopes.push(impl_scope(i));
+        syn::visit::visit_item_impl(self, i);
+        self.scopes.pop();
+    }
+    fn visit_item_trait(&mut self, i: &syn::ItemTrait) {
+        self.scopes.push(i.ident.to_string());
+        syn::visit::visit_item_trait(self, i);
+        self.scopes.pop();
+    }
+    fn visit_item_fn(&mut self, i: &syn::ItemFn) {
+        let main = core::mem::take(&mut self.main);
+        if !self.options.only_methods
+            && requires_instrument(&i.attrs, &i.sig, &i.block, self.options)
+        {
+            self.insert(i.span(), &i.attrs, &i.sig, main);
+        } else {
+            self.refresh(&i.attrs, &i.sig);
+        }
+        self.scopes.push(i.sig.ident.to_string());
+        self.visit_block(&i.block);
+        self.scopes.pop();
+    }
+    fn visit_trait_item_fn(&mut self, i: &syn::TraitItemFn) {
+        if let Some(block) = &i.default {
+            if self.options.trait_methods
+                && !self.options.only_free_fns
+                && requires_instrument(&i.attrs, &i.sig, block, self.options)
+            {
+                self.insert(i.span(), &i.attrs, &i.sig, false);
+            } else {
+                self.refresh(&i.attrs, &i.sig);
+            }
+            self.scopes.push(i.sig.ident.to_string());
+            self.visit_block(block);
+            self.scopes.pop();
+        }
+    }
+    fn visit_item_macro(&mut self, i: &syn::ItemMacro) {
+        if self.options.warn_macro_rules && i.mac.path.is_ident("macro_rules") {
+            self.warnings.extend(
+                macro_rules_fns(i.mac.tokens.clone())
+                    .into_iter()
+                    .map(|span| {
+                        (
+                            span,
+                            String::from(
+                                "Function defined in `macro_rules!` body will not be instrumented",
+                            ),
+                        )
+                    }),
+            );
+        }
+    }
+}
+
+/// Returns the spans of `fn` tokens within the given `macro_rules!` body.
+fn macro_rules_fns(tokens: proc_macro2::TokenStream) -> Vec<proc_macro2::Span> {
+    tokens
+        .into_iter()
+        .flat_map(|tree| match tree {
+            proc_macro2::TokenTree::Ident(ident) if ident == "fn" => vec![ident.span()],
+            proc_macro2::TokenTree::Group(group) => macro_rules_fns(group.stream()),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+/// Returns the instrument attribute for a given function signature, with the given `otel.name`.
+fn instrument(
+    sig: &syn::Signature,
+    level: &str,
+    options: &Options,
+    otel_name: Option<&str>,
+) -> String {
+    let instr = inner_instrument(sig, level, options, otel_name);
+    if let Some(cfg) = &options.cfg_attr {
+        format!("#[cfg_attr({cfg}, {instr})]")
+    } else {
+        format!("#[{instr}]")
+    }
+}
+
+/// Returns the instrument macro for a given function signature.
+#[cfg(not(feature = "log"))]
+fn inner_instrument(
+    sig: &syn::Signature,
+    level: &str,
+    options: &Options,
+    otel_name: Option<&str>,
+) -> String {
+    let args = skip_args(sig, options);
+    let fields = otel_name.map_or_else(String::new, |name| {
+        format!(", fields(otel.name = \"{name}\")")
+    });
+    let parent = if options.root_fns.iter().any(|root| sig.ident == root) {
+        ", parent = None"
+    } else {
+        ""
+    };
+    let ret = if options.ret && records_ret(sig) {
+        ", ret"
+    } else {
+        ""
+    };
+    let err = if options.with_err && returns_result(sig, options) {
+        ", err"
+    } else {
+        ""
+    };
+    format!(
+        "{}instrument(level = \"{level}\", skip({args}){fields}{parent}{ret}{err})",
+        options.suffix.as_deref().unwrap_or("tracing::")
+    )
+}
+
+/// Regenerates the `skip(..)` of the given instrument arguments for a given function signature,
+/// adding it after the level unless the arguments have `skip_all`.
+#[cfg(not(feature = "log"))]
+fn refresh_skip(args: &mut Vec<(String, String)>, sig: &syn::Signature, options: &Options) {
+    let regenerated = format!("skip({})", skip_args(sig, options));
+    if let Some((_, text)) = args.iter_mut().find(|(key, _)| key == "skip") {
+        *text = regenerated;
+        return;
+    }
+    if !args.iter().any(|(key, _)| key == "skip_all") {
+        let index = args
+            .iter()
+            .position(|(key, _)| key == "level")
+            .map_or(0, |index| index + 1);
+        args.insert(index, (String::from("skip"), regenerated));
+    }
+}
+
+/// Regenerates the `skip(..)` of the given instrument arguments, which `log_instrument` does not
+/// have.
+#[cfg(feature = "log")]
+fn refresh_skip(_args: &mut [(String, String)], _sig: &syn::Signature, _options: &Options) {}
+
+/// Returns the arguments of `skip(..)` for a given function signature.
+#[cfg(not(feature = "log"))]
+fn skip_args(sig: &syn::Signature, options: &Options) -> String {
+    let iter = sig.inputs.iter().flat_map(|arg| match arg {
+        syn::FnArg::Receiver(_) if options.record_receiver => Vec::new(),
+        syn::FnArg::Receiver(_) => vec![String::from("self")],
+        // Parameters of `--record-type` types are recorded rather than skipped.
+        syn::FnArg::Typed(syn::PatType { ty, .. }) if recorded_type(ty, options) => Vec::new(),
+        syn::FnArg::Typed(syn::PatType { pat, .. }) => match &**pat {
+            syn::Pat::Ident(syn::PatIdent { ident, .. }) => vec![ident.to_string()],
+            syn::Pat::Struct(syn::PatStruct { fields, .. }) => fields
+                .iter()
+                .filter_map(|f| match &f.member {
+                    syn::Member::Named(ident) => Some(ident.to_string()),
+                    syn::Member::Unnamed(_) => None,
+                })
+                .collect::<Vec<_>>(),
+            _ => Vec::new(),
+        },
+    });
+    let mut args = itertools::intersperse(iter, String::from(", ")).collect::<String>();
+    if options.trailing_comma && !args.is_empty() {
+        args.push(',');
+    }
+    args
+}
+
+/// Returns whether the function with the given signature returns a `Result` or one of the
+/// `result_aliases`, whatever its generic arguments e.g. `std::result::Result<T, Box<dyn Error>>`.
+#[cfg(not(feature = "log"))]
+fn returns_result(sig: &syn::Signature, options: &Options) -> bool {
+    let syn::ReturnType::Type(_, ty) = &sig.output else {
+        return false;
+    };
+    matches!(&**ty, syn::Type::Path(syn::TypePath { path, .. }) if path.segments.last().is_some_and(|segment| {
+        segment.ident == "Result" || options.result_aliases.iter().any(|alias| segment.ident == alias)
+    }))
+}
+
+/// Returns whether `ret` can usefully record the return value of the function with the given
+/// signature.
+///
+/// There is nothing to record for `()` and `impl Trait` values e.g. `impl Future<Output = T>`
+/// may not implement `Debug`. The output of `async fn`s is recorded when awaited.
+#[cfg(not(feature = "log"))]
+fn records_ret(sig: &syn::Signature) -> bool {
+    match &sig.output {
+        syn::ReturnType::Default => false,
+        syn::ReturnType::Type(_, ty) => match &**ty {
+            syn::Type::Tuple(syn::TypeTuple { elems, .. }) => !elems.is_empty(),
+            syn::Type::ImplTrait(_) => false,
+            _ => true,
+        },
+    }
+}
+
+/// Returns whether the given parameter type is one of the `--record-type` types.
+#[cfg(not(feature = "log"))]
+fn recorded_type(ty: &syn::Type, options: &Options) -> bool {
+    type_ident(ty).is_some_and(|ident| options.record_types.iter().any(|record| ident == record))
+}
+
+/// Returns the last segment identifier of the given type if it is a path e.g. `Vec` for
+/// `std::vec::Vec<u8>`.
+fn type_ident(ty: &syn::Type) -> Option<&syn::Ident> {
+    match ty {
+        syn::Type::Path(syn::TypePath { path, .. }) => {
+            path.segments.last().map(|segment| &segment.ident)
+        }
+        _ => None,
+    }
+}
+
+/// Returns the instrument macro for a given function signature.
+#[cfg(feature = "log")]
+fn inner_instrument(
+    _sig: &syn::Signature,
+    _level: &str,
+    options: &Options,
+    _otel_name: Option<&str>,
+) -> String {
+    format!(
+        "{}instrument",
+        options.suffix.as_deref().unwrap_or("log_instrument::")
+    )
+}
+
+/// A problem with a function found by `check`.
+pub enum Problem {
+    /// The function is missing instrumentation.
+    Missing,
+    /// The function's instrumentation has the wrong level.
+    Level {
+        /// The level found.
+        found: String,
+        /// The level expected.
+        expected: String,
+    },
+    /// The function's instrumentation has neither `skip(..)` nor `skip_all`.
+    MissingSkip,
+}
+
+/// The output of [`apply`].
+#[derive(Default)]
+#[non_exhaustive]
+pub struct Output {
+    /// The spans, identifiers and problems of the functions with problems found by `check`.
+    pub problems: Vec<(proc_macro2::Span, String, Problem)>,
+    /// The spans and messages of warnings.
+    pub warnings: Vec<(proc_macro2::Span, String)>,
+    /// The number of instrument attributes removed by `strip` or `dedupe`.
+    pub removed: usize,
+    /// The number of functions checked by `check`.
+    pub functions: usize,
+    /// The number of functions checked by `check` which are instrumented.
+    pub covered: usize,
+    /// The spans and skip reasons of the functions intentionally not instrumented, found by
+    /// `check` with `report_skipped`.
+    pub skipped: Vec<(proc_macro2::Span, &'static str)>,
+    /// The spans and explanations of the functions missing instrumentation found by `check` with
+    /// `explain`, e.g. `not instrumented, not a test, not const, not skip-attributed`.
+    pub explanations: Vec<(proc_macro2::Span, String)>,
+    /// The spans and enclosing scopes of the functions with problems found by `check` e.g.
+    /// `module::<Unit as Display>`, empty at the root of the file.
+    pub scopes: Vec<(proc_macro2::Span, String)>,
+    /// The identifiers of the path parameter types of the functions checked by `check` with
+    /// `collect_arg_types`, once per parameter.
+    pub arg_types: Vec<String>,
+    /// The number of functions instrumented by `fix`.
+    pub instrumented: usize,
+}
+impl Output {
+    /// Adds the given output of another action or Markdown block.
+    fn merge(&mut self, other: Self) {
+        self.problems.extend(other.problems);
+        self.warnings.extend(other.warnings);
+        self.removed += other.removed;
+        self.functions += other.functions;
+        self.covered += other.covered;
+        self.skipped.extend(other.skipped);
+        self.explanations.extend(other.explanations);
+        self.scopes.extend(other.scopes);
+        self.arg_types.extend(other.arg_types);
+        self.instrumented += other.instrumented;
+    }
+}
+
+/// Error for [`apply`].
+#[derive(Debug)]
+pub enum ApplyError {
+    /// Failed to read file.
+    Read(std::io::Error),
+    /// Failed to parse file to utf8.
+    Utf(core::str::Utf8Error),
+    /// Failed to parse file to syn ast.
+    Syn(syn::parse::Error),
+    /// File nesting exceeds the maximum depth.
+    Depth(usize),
+    /// Failed to get write target.
+    Target(std::io::Error),
+    /// Failed to write result to target.
+    Write(std::io::Error),
+}
+impl fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(read) => write!(f, "Failed to read file: {read}"),
+            Self::Utf(utf) => write!(f, "Failed to parse file to utf8: {utf}"),
+            Self::Syn(syn) => write!(f, "Failed to parse file to syn ast: {syn}"),
+            Self::Depth(depth) => write!(f, "File nesting exceeds the maximum depth of {depth}."),
+            Self::Target(target) => write!(f, "Failed to get write target: {target}"),
+            Self::Write(write) => write!(f, "Failed to write result to target: {write}"),
+        }
+    }
+}
+
+impl Error for ApplyError {}
+
+/// Apply the given action to the given source and outputs the result to the target produced by the
+/// given closure.
+///
+/// # Errors
+///
+/// When failing to read, parse or write the source.
+pub fn apply<R: Read, W: Write, T: FnOnce(R) -> Result<W, std::io::Error>>(
+    action: &Action,
+    options: &Options,
+    source: R,
+    target: T,
+) -> Result<Output, ApplyError> {
+    apply_actions(core::slice::from_ref(action), options, source, target)
+}
+
+/// Apply the given actions in order to the given source and outputs the result to the target
+/// produced by the given closure, see [`apply`]. The target is not produced when the result is
+/// unchanged.
+///
+/// Each action applies to the result of the previous, the source is only parsed again after an
+/// action changes it. Spans in the output refer to the source as seen by the action which produced
+/// them.
+///
+/// # Errors
+///
+/// When failing to read, parse or write the source.
+pub fn apply_actions<R: Read, W: Write, T: FnOnce(R) -> Result<W, std::io::Error>>(
+    actions: &[Action],
+    options: &Options,
+    mut source: R,
+    target: T,
+) -> Result<Output, ApplyError> {
+    let mut buf = Vec::new();
+    source.read_to_end(&mut buf).map_err(ApplyError::Read)?;
+    let original = core::str::from_utf8(&buf).map_err(ApplyError::Utf)?;
+    let mut text = String::from(original);
+
+    let mut output = Output::default();
+    let mut parsed = None;
+    for action in actions {
+        let ast = match parsed.take() {
+            Some(ast) => ast,
+            None => parse(&text, options)?,
+        };
+        let (out, res) = apply_ast(action, options, &text, &ast);
+        output.merge(res);
+        match out {
+            Some(changed) if changed != text => text = changed,
+            _ => parsed = Some(ast),
+        }
+    }
+
+    let changed = options.newline_style.apply(text);
+    if modifies(actions) && changed != original {
+        target(source)
+            .map_err(ApplyError::Target)?
+            .write_all(changed.as_bytes())
+            .map_err(ApplyError::Write)?;
+    }
+    Ok(output)
+}
+
+/// Returns whether any of the given actions modify the source, i.e. are not `check`.
+fn modifies(actions: &[Action]) -> bool {
+    actions
+        .iter()
+        .any(|action| !matches!(action, Action::Check))
+}
+
+/// Parses the given source.
+fn parse(text: &str, options: &Options) -> Result<syn::File, ApplyError> {
+    // Parsing recurses for each level of nesting so pathologically nested files overflow the
+    // stack, tokenizing does not.
+    if let Some(max_depth) = options.max_depth {
+        let tokens = text
+            .parse::<proc_macro2::TokenStream>()
+            .map_err(|err| ApplyError::Syn(err.into()))?;
+        if exceeds_depth(tokens, max_depth) {
+            return Err(ApplyError::Depth(max_depth));
+        }
+    }
+    syn::parse_file(text).or_else(|err| {
+        if !options.allow_fragments {
+            return Err(ApplyError::Syn(err));
+        }
+        // The items within the statements are visited as the items of a file, the spans are
+        // unchanged as the text is parsed as is. The error for the file is kept as it is the
+        // more helpful when neither parses.
+        let stmts = syn::parse::Parser::parse_str(syn::Block::parse_within, text)
+            .map_err(|_fragment_err| ApplyError::Syn(err))?;
+        let items = stmts
+            .into_iter()
+            .filter_map(|stmt| match stmt {
+                syn::Stmt::Item(item) => Some(item),
+                syn::Stmt::Local(_) | syn::Stmt::Expr(..) | syn::Stmt::Macro(_) => None,
+            })
+            .collect();
+        Ok(syn::File {
+            shebang: None,
+            attrs: Vec::new(),
+            items,
+        })
+    })
+}
+
+/// Applies the given action to the given parsed source, returning the resulting source for
+/// actions other than `check`.
+fn apply_ast(
+    action: &Action,
+    options: &Options,
+    text: &str,
+    ast: &syn::File,
+) -> (Option<String>, Output) {
+    match action {
+        Action::Strip => {
+            let mut visitor = StripVisitor::new(text, options.strip_level.as_deref(), options);
+            visitor.visit_file(ast);
+            if options.clean_imports {
+                visitor.clean_imports(&ast.items);
+            }
+            let removed = visitor.removed;
+            let output = Output {
+                removed,
+                ..Output::default()
+            };
+            (Some(String::from(visitor)), output)
+        }
+        Action::Dedupe => {
+            let mut visitor = DedupeVisitor(StripVisitor::new(text, None, options));
+            visitor.visit_file(ast);
+            let removed = visitor.0.removed;
+            let output = Output {
+                removed,
+                ..Output::default()
+            };
+            (Some(String::from(visitor.0)), output)
+        }
+        Action::Check => {
+            let mut visitor = CheckVisitor {
+                options,
+                lines: text.split('\n').collect(),
+                problems: Vec::new(),
+                functions: 0,
+                covered: 0,
+                skipped: Vec::new(),
+                explanations: Vec::new(),
+                scopes: Vec::new(),
+                scope: Vec::new(),
+                arg_types: Vec::new(),
+                warnings: Vec::new(),
+                inactive: false,
+            };
+            visitor.visit_file(ast);
+            let output = Output {
+                problems: visitor.problems,
+                warnings: visitor.warnings,
+                functions: visitor.functions,
+                covered: visitor.covered,
+                skipped: visitor.skipped,
+                explanations: visitor.explanations,
+                scopes: visitor.scopes,
+                arg_types: visitor.arg_types,
+                ..Output::default()
+            };
+            (None, output)
+        }
+        Action::Fix => {
+            let mut visitor = FixVisitor {
+                options,
+                list: SegmentedList {
+                    first: String::new(),
+                    inner: text
+                        .split('\n')
+                        .map(|x| (String::from(x), String::new()))
+                        .collect(),
+                    edits: Vec::new(),
+                },
+                warnings: Vec::new(),
+                main: false,
+                inserted: 0,
+                scopes: Vec::new(),
+                inactive: false,
+            };
+            visitor.visit_file(ast);
+            let warnings = core::mem::take(&mut visitor.warnings);
+            let output = Output {
+                warnings,
+                instrumented: visitor.inserted,
+                ..Output::default()
+            };
+            let import = options.ensure_import
+                && options.suffix.as_deref() == Some("")
+                && visitor.inserted > 0
+                && !ast.items.iter().any(
+                    |item| matches!(item, syn::Item::Use(item_use) if imports_instrument(&item_use.tree)),
+                );
+            let fixed = String::from(visitor);
+            if import {
+                (Some(insert_import(&fixed, ast)), output)
+            } else {
+                (Some(fixed), output)
+            }
+        }
+    }
+}
+
+/// Apply the given action to the given source, returning the resulting source and output.
+///
+/// The `check` action returns the source unchanged.
+///
+/// # Errors
+///
+/// When failing to parse the source.
+pub fn apply_str(
+    action: &Action,
+    options: &Options,
+    source: &str,
+) -> Result<(String, Output), ApplyError> {
+    let mut buf = Vec::new();
+    let output = apply(action, options, source.as_bytes(), |_| Ok(&mut buf))?;
+    // Unchanged sources are not written.
+    let text = match action {
+        Action::Check => String::from(source),
+        _ if buf.is_empty() => String::from(source),
+        _ => String::from_utf8(buf).map_err(|err| ApplyError::Utf(err.utf8_error()))?,
+    };
+    Ok((text, output))
+}
+
+/// Applies the actions to the Rust code blocks within the given Markdown source, see
+/// [`apply_actions`].
+///
+/// Code blocks fenced as `rust`, `no_run` or `ignore` are each processed as a file padded with
+/// empty lines so spans refer to lines of the Markdown. Blocks which do not parse as a file e.g.
+/// those containing bare statements are left unchanged.
+///
+/// # Errors
+///
+/// See [`apply`].
+pub fn apply_markdown<R: Read, W: Write, T: FnOnce(R) -> Result<W, std::io::Error>>(
+    actions: &[Action],
+    options: &Options,
+    mut source: R,
+    target: T,
+) -> Result<Output, ApplyError> {
+    let mut buf = Vec::new();
+    source.read_to_end(&mut buf).map_err(ApplyError::Read)?;
+    let text = core::str::from_utf8(&buf).map_err(ApplyError::Utf)?;
+    let mut lines = text.split('\n').map(String::from).collect::<Vec<_>>();
+
+    let mut output = Output::default();
+    let mut replacements = Vec::new();
+    for block in markdown_blocks(&lines) {
+        let padded = itertools::intersperse(
+            core::iter::repeat_n("", block.start).chain(
+                lines
+                    .iter()
+                    .skip(block.start)
+                    .take(block.len())
+                    .map(String::as_str),
+            ),
+            "\n",
+        )
+        .collect::<String>();
+        let mut out = Vec::new();
+        // The limit applies across the blocks of the file.
+        let block_options = Options {
+            limit: options
+                .limit
+                .map(|limit| limit.saturating_sub(output.instrumented)),
+            ..options.clone()
+        };
+        let res = match apply_actions(actions, &block_options, padded.as_bytes(), |_| Ok(&mut out))
+        {
+            Ok(res) => res,
+            Err(ApplyError::Syn(_)) => continue,
+            Err(err) => return Err(err),
+        };
+        output.merge(res);
+        // Unchanged blocks are not written.
+        if modifies(actions) && !out.is_empty() {
+            let block_text =
+                String::from_utf8(out).map_err(|err| ApplyError::Utf(err.utf8_error()))?;
+            let block_lines = block_text.split('\n').skip(block.start).map(String::from);
+            replacements.push((block, block_lines.collect::<Vec<_>>()));
+        }
+    }
+
+    if modifies(actions) {
+        // Blocks are replaced from the end so the ranges of earlier blocks remain valid.
+        for (block, block_lines) in replacements.into_iter().rev() {
+            lines.splice(block, block_lines);
+        }
+        let changed = options
+            .newline_style
+            .apply(itertools::intersperse(lines, String::from("\n")).collect::<String>());
+        if changed != text {
+            target(source)
+                .map_err(ApplyError::Target)?
+                .write_all(changed.as_bytes())
+                .map_err(ApplyError::Write)?;
+        }
+    }
+    Ok(output)
+}
+
+/// Returns the ranges of the lines within the Rust code blocks of the given Markdown lines.
+fn markdown_blocks(lines: &[String]) -> Vec<core::ops::Range<usize>> {
+    let mut blocks = Vec::new();
+    // The fence character, fence length, whether the block is Rust and its first line.
+    let mut open = None;
+    for (index, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        match open {
+            None => {
+                if let Some((c, len)) = fence(trimmed) {
+                    let info = trimmed.chars().skip(len).collect::<String>();
+                    let rust = info
+                        .split(|x: char| x == ',' || x.is_whitespace())
+                        .any(|token| matches!(token, "rust" | "no_run" | "ignore"));
+                    open = Some((c, len, rust, index + 1));
+                }
+            }
+            Some((c, len, rust, start)) => {
+                if fence(trimmed)
+                    .is_some_and(|(x, n)| x == c && n >= len && n == trimmed.chars().count())
+                {
+                    if rust {
+                        blocks.push(start..index);
+                    }
+                    open = None;
+                }
+            }
+        }
+    }
+    blocks
+}
+
+/// Returns the character and length of the code fence the given line starts with, if any.
+fn fence(line: &str) -> Option<(char, usize)> {
+    let c = line.chars().next().filter(|x| *x == '`' || *x == '~')?;
+    let len = line.chars().take_while(|x| *x == c).count();
+    (len >= 3).then_some((c, len))
+}
+
+/// Returns whether groups in the given tokens are nested deeper than the given depth.
+fn exceeds_depth(tokens: proc_macro2::TokenStream, max_depth: usize) -> bool {
+    let mut stack = vec![tokens.into_iter()];
+    while let Some(iter) = stack.last_mut() {
+        match iter.next() {
+            Some(proc_macro2::TokenTree::Group(group)) => {
+                if stack.len() > max_depth {
+                    return true;
+                }
+                stack.push(group.stream().into_iter());
+            }
+            Some(_) => {}
+            None => {
+                stack.pop();
+            }
+        }
+    }
+    false
+}
+
+/// Returns the name under which the given use tree imports `instrument`, if it imports only
+/// `instrument`.
+fn instrument_import(tree: &syn::UseTree) -> Option<String> {
+    match tree {
+        syn::UseTree::Path(syn::UsePath { tree: inner, .. }) => instrument_import(inner),
+        syn::UseTree::Name(syn::UseName { ident }) if ident == "instrument" => {
+            Some(ident.to_string())
+        }
+        syn::UseTree::Rename(syn::UseRename { ident, rename, .. }) if ident == "instrument" => {
+            Some(rename.to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether the given use tree imports `instrument`, including within groups e.g.
+/// `use tracing::{info, instrument};`.
+fn imports_instrument(tree: &syn::UseTree) -> bool {
+    match tree {
+        syn::UseTree::Path(syn::UsePath { tree: inner, .. }) => imports_instrument(inner),
+        syn::UseTree::Name(syn::UseName { ident }) => ident == "instrument",
+        syn::UseTree::Rename(syn::UseRename { rename, .. }) => rename == "instrument",
+        syn::UseTree::Glob(_) => false,
+        syn::UseTree::Group(syn::UseGroup { items, .. }) => items.iter().any(imports_instrument),
+    }
+}
+
+/// Inserts [`INSTRUMENT_IMPORT`] into the given fixed source of the given file, below its shebang
+/// and inner attributes which are never moved by `fix`.
+fn insert_import(text: &str, ast: &syn::File) -> String {
+    let below = ast
+        .attrs
+        .iter()
+        .map(|attr| attr.span().end().line)
+        .max()
+        .unwrap_or(usize::from(ast.shebang.is_some()));
+    let lines = text.split('\n').collect::<Vec<_>>();
+    let import = format!(
+        "{INSTRUMENT_IMPORT}{}",
+        line_ending(&lines, below).trim_end_matches('\n')
+    );
+    let iter = lines
+        .iter()
+        .take(below)
+        .copied()
+        .chain(core::iter::once(import.as_str()))
+        .chain(lines.iter().skip(below).copied());
+    itertools::intersperse(iter, "\n").collect::<String>()
+}
+
+/// Finds the `#[instrument]` attribute on a function, with `strict_instrument_path` only if it
+/// has the full instrument path.
+fn find_instrumented<'a>(
+    attrs: &'a [syn::Attribute],
+    options: &Options,
+) -> Option<&'a syn::Attribute> {
+    attrs.iter().find(|attr| {
+        instrument_meta(attr).is_some_and(|meta| instrument_path_matches(&meta, options))
+    })
+}
+
+/// Returns whether the given instrument meta has the full instrument path e.g.
+/// `tracing::instrument`, this is always true without `strict_instrument_path`.
+fn instrument_path_matches(meta: &syn::Meta, options: &Options) -> bool {
+    if !options.strict_instrument_path {
+        return true;
+    }
+    #[cfg(not(feature = "log"))]
+    let default = "tracing::";
+    #[cfg(feature = "log")]
+    let default = "log_instrument::";
+    let suffix = options.suffix.as_deref().unwrap_or(default);
+    // A leading `::` is not a segment so is ignored.
+    let path = itertools::join(
+        meta.path().segments.iter().map(|segment| &segment.ident),
+        "::",
+    );
+    path == format!("{}instrument", suffix.trim_start_matches("::"))
+}
+
+/// Returns the `#[instrument]` attributes on a function, with `strict_instrument_path` only those
+/// with the full instrument path.
+fn instrument_attrs<'a>(
+    attrs: &'a [syn::Attribute],
+    options: &'a Options,
+) -> impl Iterator<Item = &'a syn::Attribute> {
+    attrs.iter().filter(|attr| {
+        instrument_meta(attr).is_some_and(|meta| instrument_path_matches(&meta, options))
+    })
+}
+
+/// Returns the meta of the given instrument attribute, unwrapping `#[cfg_attr(.., instrument)]`.
+fn instrument_meta(attr: &syn::Attribute) -> Option<syn::Meta> {
+    nested_instrument_meta(&attr.meta)
+}
+
+/// Returns the given meta if it is an instrument meta, else the instrument meta nested within it
+/// if it is a `cfg_attr` meta.
+fn nested_instrument_meta(meta: &syn::Meta) -> Option<syn::Meta> {
+    let is_ident = |path: &syn::Path, name: &str| matches!(path.segments.last(), Some(syn::PathSegment { ident, .. }) if ident == name);
+    match meta {
+        syn::Meta::Path(path) if is_ident(path, "instrument") => Some(meta.clone()),
+        syn::Meta::List(list) if is_ident(&list.path, "instrument") => Some(meta.clone()),
+        syn::Meta::List(list) if is_ident(&list.path, "cfg_attr") => {
+            let nested = list
+                .parse_args_with(
+                    syn::punctuated::Punctuated::<syn::Meta, syn::Token![,]>::parse_terminated,
+                )
+                .ok()?;
+            // The 1st element is the `cfg_attr` predicate.
+            nested.iter().skip(1).find_map(nested_instrument_meta)
+        }
+        _ => None,
+    }
+}
+
+/// Returns the tokens of the value of the top-level `key = value` argument of the given
+/// instrument meta.
+fn instrument_arg(meta: &syn::Meta, key: &str) -> Option<proc_macro2::TokenStream> {
+    let syn::Meta::List(syn::MetaList { tokens, .. }) = meta else {
+        return None;
+    };
+    let is_comma = |token: &proc_macro2::TokenTree| matches!(token, proc_macro2::TokenTree::Punct(punct) if punct.as_char() == ',');
+    let mut iter = tokens.clone().into_iter();
+    while let Some(token) = iter.next() {
+        if matches!(&token, proc_macro2::TokenTree::Ident(ident) if ident == key)
+            && matches!(iter.next(), Some(proc_macro2::TokenTree::Punct(punct)) if punct.as_char() == '=')
+        {
+            return Some(iter.take_while(|t| !is_comma(t)).collect());
+        }
+    }
+    None
+}
+
+/// Returns the key, start and end of each top-level argument of the given instrument meta e.g.
+/// `skip` for `skip(x)` and `level` for `level = "debug"`.
+fn instrument_args(
+    meta: &syn::Meta,
+) -> Vec<(String, proc_macro2::LineColumn, proc_macro2::LineColumn)> {
+    let syn::Meta::List(syn::MetaList { tokens, .. }) = meta else {
+        return Vec::new();
+    };
+    let mut args = vec![Vec::new()];
+    for token in tokens.clone() {
+        match (&token, args.last_mut()) {
+            (proc_macro2::TokenTree::Punct(punct), _) if punct.as_char() == ',' => {
+                args.push(Vec::new());
+            }
+            (_, Some(arg)) => arg.push(token),
+            (_, None) => {}
+        }
+    }
+    args.into_iter()
+        .filter_map(|arg| {
+            let key = match arg.first()? {
+                proc_macro2::TokenTree::Ident(ident) => ident.to_string(),
+                _ => String::new(),
+            };
+            Some((key, arg.first()?.span().start(), arg.last()?.span().end()))
+        })
+        .collect()
+}
+
+/// Returns whether the given instrument meta has a top-level `skip(..)` or `skip_all` argument.
+fn instrument_skips(meta: &syn::Meta) -> bool {
+    let syn::Meta::List(syn::MetaList { tokens, .. }) = meta else {
+        return false;
+    };
+    tokens.clone().into_iter().any(|token| {
+        matches!(&token, proc_macro2::TokenTree::Ident(ident) if ident == "skip" || ident == "skip_all")
+    })
+}
+
+/// Returns the `level` of the given instrument attribute, defaulting to `info` like `tracing`.
+fn instrument_level(attr: &syn::Attribute) -> String {
+    let Some(tokens) = instrument_meta(attr).and_then(|meta| instrument_arg(&meta, "level")) else {
+        return String::from("info");
+    };
+    match syn::parse2::<syn::Expr>(tokens.clone()) {
+        // e.g. `level = "debug"`.
+        Ok(syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Str(lit),
+            ..
+        })) => lit.value(),
+        // e.g. `level = Level::DEBUG`.
+        Ok(syn::Expr::Path(syn::ExprPath { path, .. })) => {
+            path.segments.last().map_or_else(String::new, |segment| {
+                segment.ident.to_string().to_lowercase()
+            })
+        }
+        // e.g. `level = 1`.
+        Ok(syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Int(lit),
+            ..
+        })) => match lit.base10_digits() {
+            "1" => String::from("trace"),
+            "2" => String::from("debug"),
+            "3" => String::from("info"),
+            "4" => String::from("warn"),
+            "5" => String::from("error"),
+            _ => tokens.to_string(),
+        },
+        _ => tokens.to_string(),
+    }
+}
+
+/// Returns whether a function with the given attributes, signature and body is missing
+/// instrumentation.
+fn requires_instrument(
+    attrs: &[syn::Attribute],
+    sig: &syn::Signature,
+    block: &syn::Block,
+    options: &Options,
+) -> bool {
+    let attr = check_attributes(attrs, options);
+    !attr.instrumented
+        && skip_reason(&attr, attrs, sig, options).is_none()
+        && options.min_branches.is_none_or(|min| {
+            let mut visitor = BranchVisitor(0);
+            visitor.visit_block(block);
+            visitor.0 >= min
+        })
+        && options.min_args.is_none_or(|min| {
+            let receiver = usize::from(sig.receiver().is_some() && !options.min_args_count_self);
+            sig.inputs.len() - receiver >= min
+        })
+        && changed(attrs, sig, block, options)
+        && !(options.skip_trivial && trivial(block))
+        && (options.only_fns.is_empty() || options.only_fns.iter().any(|name| sig.ident == name))
+}
+
+/// Returns why the function with the given description and signature requires instrumentation,
+/// listing the checks which would otherwise skip it, see [`skip_reason`].
+fn explanation(desc: &Desc, sig: &syn::Signature, options: &Options) -> String {
+    let constness = if sig.constness.is_none() {
+        "not const"
+    } else {
+        "const included"
+    };
+    [
+        (!desc.instrumented, "not instrumented"),
+        (!desc.test, "not a test"),
+        (true, constness),
+        (!desc.skipped, "not skip-attributed"),
+        (
+            options.skip_deprecated && !desc.deprecated,
+            "not deprecated",
+        ),
+        (
+            options.skip_entry_macros && !desc.entry,
+            "not an entry point",
+        ),
+        (options.skip_cfg_test && !desc.cfg_test, "not cfg(test)"),
+        (options.skip_extern_abi && sig.abi.is_none(), "not extern"),
+        (!options.skip_cfg.is_empty(), "no skipped cfg"),
+    ]
+    .into_iter()
+    .filter_map(|(checked, reason)| checked.then_some(reason))
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+/// Returns why the function with the given description, attributes and signature is
+/// intentionally not instrumented, if it is.
+fn skip_reason(
+    desc: &Desc,
+    attrs: &[syn::Attribute],
+    sig: &syn::Signature,
+    options: &Options,
+) -> Option<&'static str> {
+    [
+        (desc.skipped, "skip"),
+        (desc.test, "test"),
+        (desc.deprecated && options.skip_deprecated, "deprecated"),
+        (desc.entry && options.skip_entry_macros, "entry"),
+        (desc.cfg_test && options.skip_cfg_test, "cfg(test)"),
+        (sig.constness.is_some() && !options.include_const, "const"),
+        (sig.abi.is_some() && options.skip_extern_abi, "extern"),
+        (skip_cfg(attrs, options), "cfg"),
+    ]
+    .into_iter()
+    .find_map(|(skipped, reason)| skipped.then_some(reason))
+}
+
+/// Returns whether the given function body is a single trivial expression e.g. `self.x`.
+fn trivial(block: &syn::Block) -> bool {
+    match block.stmts.as_slice() {
+        [syn::Stmt::Expr(expr, None)] => trivial_expr(expr),
+        _ => false,
+    }
+}
+
+/// Returns whether the given expression is a field access, literal, path or simple arithmetic
+/// of these, which have no side effects.
+fn trivial_expr(expr: &syn::Expr) -> bool {
+    match expr {
+        syn::Expr::Lit(_) | syn::Expr::Path(_) => true,
+        syn::Expr::Field(syn::ExprField { base, .. }) => trivial_expr(base),
+        syn::Expr::Binary(syn::ExprBinary { left, right, .. }) => {
+            trivial_expr(left) && trivial_expr(right)
+        }
+        syn::Expr::Unary(syn::ExprUnary { expr: inner, .. })
+        | syn::Expr::Paren(syn::ExprParen { expr: inner, .. })
+        | syn::Expr::Reference(syn::ExprReference { expr: inner, .. })
+        | syn::Expr::Cast(syn::ExprCast { expr: inner, .. }) => trivial_expr(inner),
+        _ => false,
+    }
+}
+
+/// Returns whether the function overlaps any of the `changed_lines`, or if they are not set.
+fn changed(
+    attrs: &[syn::Attribute],
+    sig: &syn::Signature,
+    block: &syn::Block,
+    options: &Options,
+) -> bool {
+    options.changed_lines.as_ref().is_none_or(|ranges| {
+        let start = attrs
+            .first()
+            .map_or_else(|| sig.span(), syn::spanned::Spanned::span)
+            .start()
+            .line;
+        let end = block.span().end().line;
+        ranges
+            .iter()
+            .any(|range| range.start <= end && start < range.end)
+    })
+}
+
+/// Visitor counting the branch points (`if`s, `match` arms, `while`s, `for`s and `?`s) in a
+/// function body, excluding those in nested items.
+struct BranchVisitor(usize);
+impl syn::visit::Visit<'_> for BranchVisitor {
+    fn visit_expr_if(&mut self, i: &syn::ExprIf) {
+        self.0 += 1;
+        syn::visit::visit_expr_if(self, i);
+    }
+    fn visit_arm(&mut self, i: &syn::Arm) {
+        self.0 += 1;
+        syn::visit::visit_arm(self, i);
+    }
+    fn visit_expr_while(&mut self, i: &syn::ExprWhile) {
+        self.0 += 1;
+        syn::visit::visit_expr_while(self, i);
+    }
+    fn visit_expr_for_loop(&mut self, i: &syn::ExprForLoop) {
+        self.0 += 1;
+        syn::visit::visit_expr_for_loop(self, i);
+    }
+    fn visit_expr_try(&mut self, i: &syn::ExprTry) {
+        self.0 += 1;
+        syn::visit::visit_expr_try(self, i);
+    }
+    fn visit_item(&mut self, _i: &syn::Item) {}
+}
+
+/// Returns whether the impl method with the given signature is one of the `--skip-method` methods
+/// or all methods are skipped with `only_free_fns`.
+fn skip_method(sig: &syn::Signature, options: &Options) -> bool {
+    options.only_free_fns
+        || options
+            .skip_methods
+            .iter()
+            .any(|method| sig.ident == method)
+}
+
+/// Returns whether any `#[cfg(...)]` attribute has a predicate matching `--skip-cfg`.
+fn skip_cfg(attrs: &[syn::Attribute], options: &Options) -> bool {
+    attrs.iter().any(|attr| match &attr.meta {
+        syn::Meta::List(syn::MetaList { path, tokens, .. }) if path.is_ident("cfg") => {
+            let predicate = tokens.to_string();
+            options
+                .skip_cfg
+                .iter()
+                .any(|skip| predicate.contains(skip.as_str()))
+        }
+        _ => false,
+    })
+}
+
+/// The description of attributes on a function signature we care about.
+struct Desc {
+    /// Does the function have the `#[tracing::instrument]` attribute macro?
+    instrumented: bool,
+    /// Does the function have the `#[clippy_tracing_attributes::clippy_tracing_skip]` attribute macro?
+    skipped: bool,
+    /// Does the function have the `#[test]` attribute macro?
+    test: bool,
+    /// Does the function have the `#[deprecated]` attribute?
+    deprecated: bool,
+    /// Does the function have an entry point attribute macro e.g. `#[tokio::main]`?
+    entry: bool,
+    /// Does the function have the `#[cfg(test)]` or `#[cfg(all(test, ..))]` attribute?
+    cfg_test: bool,
+}
+
+/// The paths of the entry point attribute macros skipped with `skip_entry_macros`.
+const ENTRY_MACROS: [&str; 3] = ["tokio::main", "async_std::main", "actix_web::main"];
+
+/// The identifiers of test attributes e.g. `#[test]` or `#[kani::proof]`.
+const TEST_ATTRS: [&str; 2] = ["test", "proof"];
+
+/// The identifier of the skip attribute `#[clippy_tracing_attributes::clippy_tracing_skip]`.
+const SKIP_ATTR: &str = "clippy_tracing_skip";
+
+/// Returns the identifiers of the attributes recognized as instrumentation, tests, skips and entry
+/// points with the given options, by category.
+#[must_use]
+pub fn recognized_attributes(options: &Options) -> Vec<(&'static str, Vec<String>)> {
+    let instrument = core::iter::once(String::from("instrument"))
+        .chain(options.treats_as_instrumented.iter().cloned())
+        .collect();
+    vec![
+        ("instrument", instrument),
+        ("test", TEST_ATTRS.map(String::from).to_vec()),
+        ("skip", vec![String::from(SKIP_ATTR)]),
+        ("entry", ENTRY_MACROS.map(String::from).to_vec()),
+    ]
+}
+
+// A function is considered instruments if it has the `#[instrument]` attribute or the `#[test]`
+// attribute.
+/// Returns a tuple where the 1st element is whether `tracing::instrument` is found in the list of
+/// attributes and the 2nd is whether `clippy_tracing_attributes::skip` is found in the list of
+/// attributes.
+fn check_attributes(attrs: &[syn::Attribute], options: &Options) -> Desc {
+    let mut instrumented = false;
+    let mut skipped = false;
+    let mut test = false;
+    let mut deprecated = false;
+    let mut entry = false;
+    let mut cfg_test = false;
+
+    for attr in attrs {
+        // Match `#[instrument]` or `#[cfg_attr(.., instrument)]`, including within nested
+        // `cfg_attr`s.
+        if instrument_meta(attr).is_some_and(|meta| instrument_path_matches(&meta, options)) {
+            instrumented = true;
+        }
+
+        // Match `--treats-as-instrumented` wrappers e.g. `#[traced]`.
+        if matches!(attr.path().segments.last(), Some(syn::PathSegment { ident, .. }) if options.treats_as_instrumented.iter().any(|wrapper| ident == wrapper))
+        {
+            instrumented = true;
+        }
+
+        // Match `#[test]` or `#[kani::proof]`.
+        if match &attr.meta {
+            syn::Meta::List(syn::MetaList { path, .. }) => {
+                matches!(path.segments.last(), Some(syn::PathSegment { ident, .. }) if ident == "proof")
+            }
+            syn::Meta::Path(syn::Path { segments, .. }) => {
+                matches!(segments.last(), Some(syn::PathSegment { ident, .. }) if TEST_ATTRS.iter().any(|test_attr| ident == test_attr))
+            }
+            syn::Meta::NameValue(_) => false,
+        } {
+            test = true;
+        }
+
+        // Match `#[clippy_tracing_skip]`.
+        if match &attr.meta {
+            syn::Meta::List(syn::MetaList { path, .. }) => {
+                matches!(path.segments.last(), Some(syn::PathSegment { ident, .. }) if ident == SKIP_ATTR)
+            }
+            syn::Meta::Path(syn::Path { segments, .. }) => {
+                matches!(segments.last(), Some(syn::PathSegment { ident, .. }) if ident == SKIP_ATTR)
+            }
+            syn::Meta::NameValue(_) => false,
+        } {
+            skipped = true;
+        }
+
+        // Match `#[deprecated]`, `#[deprecated = ".."]` or `#[deprecated(..)]`.
+        if matches!(attr.path().segments.last(), Some(syn::PathSegment { ident, .. }) if ident == "deprecated")
+        {
+            deprecated = true;
+        }
+
+        // Match `#[tokio::main]` or `#[tokio::main(..)]`.
+        let path = itertools::join(
+            attr.path().segments.iter().map(|segment| &segment.ident),
+            "::",
+        );
+        if ENTRY_MACROS.contains(&path.as_str()) {
+            entry = true;
+        }
+
+        // Match `#[cfg(test)]` or `#[cfg(all(test, ..))]`.
+        if attr.path().is_ident("cfg")
+            && attr
+                .parse_args::<syn::Meta>()
+                .is_ok_and(|meta| test_predicate(&meta))
+        {
+            cfg_test = true;
+        }
+    }
+    Desc {
+        instrumented,
+        skipped,
+        test,
+        deprecated,
+        entry,
+        cfg_test,
+    }
+}
+
+/// Returns whether the given `cfg` predicate only holds when compiling tests, either `test` or
+/// `all(..)` containing `test`.
+fn test_predicate(meta: &syn::Meta) -> bool {
+    match meta {
+        syn::Meta::Path(path) => path.is_ident("test"),
+        syn::Meta::List(list) if list.path.is_ident("all") => list
+            .parse_args_with(
+                syn::punctuated::Punctuated::<syn::Meta, syn::Token![,]>::parse_terminated,
+            )
+            .is_ok_and(|predicates| predicates.iter().any(test_predicate)),
+        syn::Meta::List(_) | syn::Meta::NameValue(_) => false,
+    }
+}
+
+/// Returns whether the given module attributes have a `#[cfg(feature = "..")]` predicate for a
+/// feature which is not in `active_features`, other predicates are not evaluated.
+fn inactive_feature(attrs: &[syn::Attribute], options: &Options) -> bool {
+    let Some(active_features) = &options.active_features else {
+        return false;
+    };
+    attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("cfg"))
+        .filter_map(|attr| attr.parse_args::<syn::Meta>().ok())
+        .any(|meta| match meta {
+            syn::Meta::NameValue(syn::MetaNameValue {
+                path,
+                value:
+                    syn::Expr::Lit(syn::ExprLit {
+                        lit: syn::Lit::Str(lit),
+                        ..
+                    }),
+                ..
+            }) if path.is_ident("feature") => !active_features.contains(&lit.value()),
+            _ => false,
+        })
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fix() {
+        const GIVEN: &str = "fn add(lhs: i32, rhs: i32) -> i32 {\n    lhs + rhs\n}";
+        #[cfg(not(feature = "log"))]
+        const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip(lhs, rhs))]\nfn add(lhs: i32, rhs: i32) -> i32 {\n    lhs + rhs\n}";
+        #[cfg(feature = "log")]
+        const EXPECTED: &str =
+            "#[log_instrument::instrument]\nfn add(lhs: i32, rhs: i32) -> i32 {\n    lhs + rhs\n}";
+        let (text, output) = apply_str(&Action::Fix, &Options::default(), GIVEN).unwrap();
+        assert_eq!(text, EXPECTED);
+        assert!(output.problems.is_empty());
+        assert!(output.warnings.is_empty());
+    }
+
+    #[test]
+    fn check() {
+        const GIVEN: &str = "fn main() {}\n#[test]\nfn my_test() {}";
+        let (text, output) = apply_str(&Action::Check, &Options::default(), GIVEN).unwrap();
+        assert_eq!(text, GIVEN);
+        assert_eq!(output.problems.len(), 1);
+        let (span, ident, problem) = &output.problems[0];
+        assert_eq!((span.start().line, span.start().column), (1, 0));
+        assert_eq!(ident, "main");
+        assert!(matches!(problem, Problem::Missing));
+    }
+
+    #[test]
+    fn strip() {
+        const GIVEN: &str =
+            "impl Unit {\n    #[tracing::instrument(level = \"trace\", skip())]\n    fn one() {}\n}";
+        const EXPECTED: &str = "impl Unit {\n    fn one() {}\n}";
+        let (text, output) = apply_str(&Action::Strip, &Options::default(), GIVEN).unwrap();
+        assert_eq!(text, EXPECTED);
+        assert!(output.problems.is_empty());
+    }
+    #[test]
+    fn dedupe() {
+        const GIVEN: &str = "#[tracing::instrument(level = \"trace\", skip())]\n#[inline]\n#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}";
+        const EXPECTED: &str =
+            "#[tracing::instrument(level = \"trace\", skip())]\n#[inline]\nfn one() {}";
+        let (text, output) = apply_str(&Action::Dedupe, &Options::default(), GIVEN).unwrap();
+        assert_eq!(text, EXPECTED);
+        assert_eq!(output.removed, 1);
+    }
+
+    #[test]
+    fn error() {
+        let result = apply_str(&Action::Check, &Options::default(), "fn main() {");
+        assert!(result.is_err());
+    }
+}
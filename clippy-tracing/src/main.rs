@@ -14,36 +14,70 @@
     clippy::print_stderr,
     clippy::wildcard_enum_match_arm,
     clippy::arithmetic_side_effects,
-    clippy::single_char_lifetime_names
+    clippy::single_char_lifetime_names,
+    clippy::arbitrary_source_item_ordering,
+    clippy::allow_attributes_without_reason,
+    clippy::struct_excessive_bools
 )]
 
 extern crate alloc;
 
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
 use alloc::fmt;
+use alloc::sync::Arc;
+use clap::builder::{EnumValueParser, TypedValueParser as _};
 use clap::{Parser, ValueEnum};
-use std::collections::HashMap;
+use clippy_tracing::{
+    apply_actions, apply_markdown, recognized_attributes, Action, ApplyError, Behavior, Confirm,
+    NewlineStyle, Options, Problem, Version,
+};
+use core::cell::Cell;
+use core::num::NonZeroUsize;
+use core::ops::Range;
+use core::time::Duration;
 use std::fs::OpenOptions;
 use std::io::{Read, Write};
-use std::path::PathBuf;
-use syn::spanned::Spanned;
-use syn::visit::Visit;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::time::SystemTime;
 use walkdir::WalkDir;
 
-use std::error::Error;
+use core::error::Error;
 use std::process::ExitCode;
 
 /// The command line arguments for the application.
 #[derive(Parser)]
 struct CommandLineArgs {
-    /// The action to take.
-    #[arg(long)]
-    action: Action,
+    /// The actions to take, applied in the given order e.g. `--action fix --action check` checks
+    /// the fixed source.
+    #[arg(
+        long,
+        required_unless_present_any = ["doctor", "list_recognized"],
+        value_parser = EnumValueParser::<ActionArg>::new().map(ActionArg::action)
+    )]
+    action: Vec<Action>,
     /// The path to look in.
     #[arg(long)]
     path: Option<PathBuf>,
+    /// Reads the source from stdin and writes the result of the actions to stdout rather than
+    /// processing files under `--path`, for editor format-on-save integrations. Problems found by
+    /// `check` are reported as for files.
+    #[arg(long, conflicts_with = "path")]
+    diff_against_stdin: bool,
+    /// Prompts on stdin before `fix` instruments each function, answering `y` or `n` for the
+    /// function, `all` for it and all later functions, or `quit` to instrument no more functions.
+    /// Files after quitting are not written.
+    #[arg(long, conflicts_with = "diff_against_stdin")]
+    interactive: bool,
+    /// Writes the results of `fix` and `strip` to a mirror of the `--path` tree rooted at this
+    /// directory instead of overwriting the files in place e.g. `--output-dir fixed` writes
+    /// `src/main.rs` to `fixed/src/main.rs`. An output directory within `--path` is not walked.
+    #[arg(long, conflicts_with_all = ["diff_against_stdin", "dry_run"])]
+    output_dir: Option<PathBuf>,
     /// When adding instrumentation use a custom suffix e.g.
     /// `tracing::instrument` vs `my::custom::suffix::instrument`.
-    #[arg(long)]
+    #[arg(long, env = "CLIPPY_TRACING_SUFFIX")]
     suffix: Option<String>,
     /// Whether to add a `cfg_attr` condition e.g.
     /// `#[cfg_attr(feature = "tracing", log_instrument::instrument)]` vs
@@ -51,466 +85,1962 @@ struct CommandLineArgs {
     #[arg(long)]
     cfg_attr: Option<String>,
     /// Sub-paths which contain any of the strings from this list will be ignored.
-    #[arg(long, value_delimiter = ',')]
+    #[arg(long, value_delimiter = ',', env = "CLIPPY_TRACING_EXCLUDE")]
     exclude: Vec<String>,
+    /// Pins the default behaviours to those of a prior release e.g. `--compat 0.7`, explicit
+    /// flags still take precedence.
+    #[arg(long)]
+    compat: Option<Version>,
+    /// A file listing known missing instrumentation for `check` to ignore, each line is
+    /// `{path}:{function}` where functions within modules, impls, traits or other functions are
+    /// prefixed by these e.g. `src/lib.rs:Unit::new` or `src/lib.rs:<Unit as Default>::default`.
+    /// Incorrect levels and missing skips are suffixed by `:level` and `:skip`. Paths are relative
+    /// to the `--vcs-root` if given, else to `--path`, so the keys are the same in any checkout.
+    #[arg(long)]
+    baseline: Option<PathBuf>,
+    /// Reports the paths of functions found by `check` relative to this directory e.g. the root
+    /// of a monorepo, these are also the paths in the `--baseline`.
+    #[arg(long)]
+    vcs_root: Option<PathBuf>,
+    /// Uses the nearest directory enclosing `--path` which contains `.git` as the `--vcs-root`.
+    #[arg(long, conflicts_with = "vcs_root")]
+    detect_vcs_root: bool,
+    /// Writes all problems found by `check` to the `--baseline` file instead of failing.
+    #[arg(long, requires = "baseline")]
+    write_baseline: bool,
+    /// Whether to instrument `const fn`s, `#[instrument]` does not support const contexts so
+    /// this may produce code which fails to compile.
+    #[arg(long)]
+    include_const: bool,
+    /// Only processes files modified since this RFC 3339 timestamp e.g. `2024-01-31T12:00:00Z` or
+    /// this duration before now e.g. `90s`, `30m`, `12h`, `7d` or `2w`.
+    #[arg(long)]
+    modified_since: Option<Since>,
+    /// Skips generated code within `out` directories below a `target` directory and within the
+    /// `OUT_DIR` directory, which only matter with `--include-target` or an unusual layout.
+    #[arg(long)]
+    skip_out_dir: bool,
+    /// Processes `build.rs` files, which are skipped by default as build scripts usually run
+    /// before tracing is set up.
+    #[arg(long)]
+    include_build_rs: bool,
+    /// Skips directories containing a file with this name e.g. `.notrace`, along with everything
+    /// below them.
+    #[arg(long)]
+    exclude_marker: Option<String>,
+    /// Whether to process files within `target` and `.git` directories.
+    #[arg(long)]
+    include_target: bool,
+    /// Whether to instrument trait methods with default implementations, as well as free
+    /// functions and impl methods.
+    #[arg(long)]
+    trait_methods: bool,
+    /// Inserts instrumentation immediately below attributes with any of these identifiers e.g.
+    /// `--place-below cfg` inserts it below `#[cfg(...)]` rather than at the top.
+    #[arg(long, value_delimiter = ',')]
+    place_below: Vec<String>,
+    /// Makes `check` also fail for instrumentation with a different `level` than this e.g.
+    /// `--enforce-level trace`.
+    #[arg(long)]
+    enforce_level: Option<String>,
+    /// The number of threads reading files, when this and `--parse-threads` are both 1 files are
+    /// processed sequentially otherwise they are processed in a pipeline where reading threads
+    /// pass files to parsing threads.
+    #[arg(long, default_value_t = NonZeroUsize::MIN)]
+    read_threads: NonZeroUsize,
+    /// The number of threads parsing files and applying the action, see `--read-threads`.
+    #[arg(long, default_value_t = NonZeroUsize::MIN)]
+    parse_threads: NonZeroUsize,
+    /// Skips functions with a `#[cfg(...)]` attribute whose predicate contains any of these strings
+    /// e.g. `--skip-cfg windows` skips functions with `#[cfg(windows)]`.
+    #[arg(long, value_delimiter = ',')]
+    skip_cfg: Vec<String>,
+    /// Emit a warning for each `fn` within a `macro_rules!` body, as expanded functions are not
+    /// instrumented.
+    #[arg(long)]
+    warn_macro_rules: bool,
+    /// Emit a warning for each `#[track_caller]` function instrumented, as the instrumentation
+    /// may change the location reported by `Location::caller()`.
+    #[arg(long)]
+    warn_track_caller: bool,
+    /// Emit a warning for each function missing instrumentation found by `check` which has a
+    /// commented out instrument attribute above it e.g. `// #[tracing::instrument]`.
+    #[arg(long)]
+    warn_commented_instrument: bool,
+    /// When stripping, also remove top-level `use` imports of `instrument` which become unused.
+    #[arg(long)]
+    clean_imports: bool,
+    /// When stripping, only remove instrument attributes of this level e.g. `trace`, attributes
+    /// without a level are `info`.
+    #[arg(long)]
+    strip_level: Option<String>,
+    /// Skips functions with a `#[cfg(test)]` attribute, unlike `--skip-cfg test` this does not
+    /// skip predicates which merely contain `test` e.g. `#[cfg(not(test))]`.
+    #[arg(long)]
+    skip_cfg_test: bool,
+    /// The enabled features, functions within inline modules gated by `#[cfg(feature = "..")]`
+    /// for any other feature are not instrumented. Without values no features are enabled.
+    #[arg(long, value_delimiter = ',', num_args = 0..)]
+    active_features: Option<Vec<String>>,
+    /// Only instruments free functions, not impl or trait methods.
+    #[arg(long, conflicts_with = "only_methods")]
+    only_free_fns: bool,
+    /// Only instruments impl and trait methods, not free functions.
+    #[arg(long)]
+    only_methods: bool,
+    /// Makes `check` print how often each parameter type appears across all functions, most common
+    /// first, to help choose `--record-type`s. Only path types are counted by their last segment
+    /// e.g. `Vec` for `std::vec::Vec<u8>`, as these are what `--record-type` matches.
+    #[arg(long)]
+    collect_args_stats: bool,
+    /// Makes `check` also print the functions which are intentionally not instrumented with the
+    /// reason e.g. `src/main.rs:3: skipped (const)`.
+    #[arg(long)]
+    report_uninstrumentable: bool,
+    /// Makes `check` explain why each function missing instrumentation is flagged e.g.
+    /// `not instrumented, not a test, not const, not skip-attributed`.
+    #[arg(long)]
+    explain: bool,
+    /// The minimum number of blank lines between inserted instrumentation and the preceding item
+    /// or comment, missing blank lines are added e.g. `--gap-lines 1`.
+    #[arg(long, default_value_t = 0)]
+    gap_lines: usize,
+    /// Sets the OpenTelemetry span name of inserted instrumentation to the path of the function
+    /// within its file e.g. `fields(otel.name = "module::Type::method")`.
+    #[arg(long)]
+    otel_name: bool,
+    /// Exits with an error when any warning is emitted, even with `--quiet`.
+    #[arg(long)]
+    fail_on_warnings: bool,
+    /// Exits with an error when no files are processed e.g. as all are excluded, which may be a
+    /// misconfigured `--path` or `--exclude`.
+    #[arg(long)]
+    error_on_empty: bool,
+    /// When fixing with `--suffix ""`, also insert `use tracing::instrument;` into files which
+    /// gained instrumentation and do not already import it.
+    #[arg(long)]
+    ensure_import: bool,
+    /// Parses files which are not valid modules as a sequence of statements, such as fragments
+    /// which are `include!`d into a function body, rather than failing.
+    #[arg(long)]
+    allow_fragments: bool,
+    /// Skips files which fail to parse with a warning rather than failing, `check` still only
+    /// fails for missing instrumentation in the files which parse.
+    #[arg(long)]
+    skip_parse_errors: bool,
+    /// The maximum nesting depth of a file e.g. `--max-depth 64`, deeper files are rejected rather
+    /// than overflowing the stack. Without it the depth is not limited.
+    #[arg(long)]
+    max_depth: Option<usize>,
+    /// Only instruments functions with at least this many branch points (`if`s, `match` arms,
+    /// `while`s, `for`s and `?`s) e.g. `--min-branches 3`.
+    #[arg(long)]
+    min_branches: Option<usize>,
+    /// Only instruments functions with at least this many parameters, excluding the receiver
+    /// e.g. `&self` unless `--min-args-count-self`.
+    #[arg(long)]
+    min_args: Option<usize>,
+    /// Counts the receiver e.g. `&self` towards `--min-args`.
+    #[arg(long, requires = "min_args")]
+    min_args_count_self: bool,
+    /// Also writes the problems found by `check` to this file, one per line.
+    #[arg(long)]
+    report_file: Option<PathBuf>,
+    /// After fixing, runs `cargo check` on the package enclosing `--path` and fails if it does not
+    /// compile. The fixed files must be written in place so this excludes `--dry-run` and
+    /// `--output-dir`.
+    #[arg(long, conflicts_with_all = ["dry_run", "output_dir"])]
+    compile_check: bool,
+    /// Prints the number of files processed to stderr after each file.
+    #[arg(long)]
+    progress: bool,
+    /// Suppresses warnings and progress on stderr.
+    #[arg(long)]
+    quiet: bool,
+    /// Does not write any changes, `strip` then prints the number of instrument attributes it
+    /// would remove.
+    #[arg(long)]
+    dry_run: bool,
+    /// Prints the identifiers of the attributes recognized as instrumentation, tests, skips and
+    /// entry points, including `--treats-as-instrumented`, then exits without processing any
+    /// files.
+    #[arg(long)]
+    list_recognized: bool,
+    /// Prints the effective configuration as TOML then exits without processing any files.
+    #[arg(long)]
+    print_config: bool,
+    /// Prints diagnostics of the setup, i.e. the version, whether `rustfmt` is found and whether
+    /// the path exists, followed by the effective configuration, then exits without processing
+    /// any files.
+    #[arg(long)]
+    doctor: bool,
+    /// The level free functions named `main` at the root of a file are instrumented with, other
+    /// functions use the `--level` e.g. `--main-level info`.
+    #[arg(long)]
+    main_level: Option<String>,
+    /// The level functions are instrumented with.
+    #[arg(long, env = "CLIPPY_TRACING_LEVEL", default_value = "trace")]
+    level: String,
+    /// Records rather than skips parameters whose type has any of these identifiers as its last
+    /// path segment e.g. `--record-type Uuid,IpAddr`.
+    #[arg(long, value_delimiter = ',')]
+    record_type: Vec<String>,
+    /// Records rather than skips `self`, which requires `Self: Debug`, this has no effect with
+    /// the `log` feature.
+    #[arg(long)]
+    record_receiver: bool,
+    /// Skips impl methods with any of these identifiers e.g. `--skip-method fmt,clone`.
+    #[arg(long, value_delimiter = ',')]
+    skip_method: Vec<String>,
+    /// Skips common boilerplate impl methods: `eq`, `ne`, `cmp`, `partial_cmp`, `hash`, `clone`,
+    /// `fmt` and `drop`.
+    #[arg(long)]
+    skip_boilerplate: bool,
+    /// Skips functions with a `#[deprecated]` attribute.
+    #[arg(long)]
+    skip_deprecated: bool,
+    /// Also processes the `rust`, `no_run` and `ignore` code blocks in `.md` files.
+    #[arg(long)]
+    markdown: bool,
+    /// Only checks or fixes functions overlapping lines changed since the given git ref, as found
+    /// by `git diff`.
+    #[arg(long, value_name = "REF")]
+    only_changed_functions: Option<String>,
+    /// Records the return values of functions returning a value other than `()` or `impl Trait`,
+    /// this has no effect with the `log` feature.
+    #[arg(long)]
+    ret: bool,
+    /// Follows inserted instrumentation with a `// clippy-tracing generated` comment.
+    #[arg(long)]
+    tag_comment: bool,
+    /// Treats attributes with any of these identifiers as instrumentation e.g.
+    /// `--treats-as-instrumented traced` for a `#[traced]` attribute macro which applies
+    /// `tracing::instrument` itself.
+    #[arg(long, value_delimiter = ',')]
+    treats_as_instrumented: Vec<String>,
+    /// Only treats attributes with the full path of the instrument attribute as instrumentation
+    /// e.g. `tracing::instrument` or `{suffix}instrument` with `--suffix`, rather than any
+    /// attribute whose path ends in `instrument` e.g. `#[other::instrument]`.
+    #[arg(long)]
+    strict_instrument_path: bool,
+    /// Skips functions whose body is a single field access, literal, path or simple arithmetic
+    /// of these e.g. `fn x(&self) -> u32 { self.x }`.
+    #[arg(long)]
+    skip_trivial: bool,
+    /// The exit code when `check` finds missing instrumentation, codes above 255 are clamped to
+    /// 255.
+    #[arg(long, default_value_t = 2)]
+    missing_exit_code: u32,
+    /// Prints at most this many problems found by `check` followed by a `... and {n} more` line,
+    /// the exit code is unaffected. All files are checked, as with `--baseline`, rather than only
+    /// the first problem being reported.
+    #[arg(long)]
+    max_output: Option<usize>,
+    /// Instruments at most this many functions with `fix` across all files, leaving the rest for
+    /// later runs. Files after the limit is reached are not written.
+    #[arg(long)]
+    limit: Option<usize>,
+    /// Prints a JSON summary of `check` to stderr e.g.
+    /// `{"files_scanned": 2, "functions_total": 5, "missing": 1}`.
+    #[arg(long)]
+    summary_json: bool,
+    /// Prints the number of instrumented functions and functions missing instrumentation found
+    /// by `check` in each file, most missing first.
+    #[arg(long)]
+    by_file: bool,
+    /// Writes the `--summary-json` summary to this file rather than stderr.
+    #[arg(long, requires = "summary_json")]
+    summary_file: Option<PathBuf>,
+    /// The line endings of modified files, `auto` keeps the line ending of each line.
+    #[arg(
+        long,
+        default_value = "auto",
+        value_parser = EnumValueParser::<NewlineStyleArg>::new().map(NewlineStyleArg::style)
+    )]
+    newline_style: NewlineStyle,
+    /// Makes `check` also fail for instrumentation without `skip(..)` or `skip_all`, which may
+    /// record arguments unintentionally.
+    #[arg(long)]
+    require_skip: bool,
+    /// Only checks or fixes functions with any of these identifiers e.g.
+    /// `--only-fn main --only-fn run`.
+    #[arg(long, value_delimiter = ',')]
+    only_fn: Vec<String>,
+    /// Instruments functions with any of these identifiers with `parent = None` so they start
+    /// new traces e.g. `--root-fn worker`.
+    #[arg(long, value_delimiter = ',')]
+    root_fn: Vec<String>,
+    /// Makes `fix` regenerate the `skip(..)` of existing instrumentation in place, keeping its other
+    /// arguments, e.g. after parameters change.
+    #[arg(long)]
+    refresh: bool,
+    /// Records the errors of functions returning a `Result`, this has no effect with the `log`
+    /// feature.
+    #[arg(long)]
+    with_err: bool,
+    /// Ends non-empty `skip(..)` lists with a trailing comma e.g. `skip(a, b,)`, this has no
+    /// effect with the `log` feature.
+    #[arg(long)]
+    trailing_comma: bool,
+    /// Treats return types with any of these identifiers as `Result`s for `--with-err` e.g.
+    /// `--result-alias Fallible`.
+    #[arg(long, value_delimiter = ',')]
+    result_alias: Vec<String>,
+    /// Skips functions with an explicit ABI e.g. `#[no_mangle] pub extern "C" fn callback() {}`.
+    #[arg(long)]
+    skip_extern_abi: bool,
+    /// Skips functions with a `#[tokio::main]`, `#[async_std::main]` or `#[actix_web::main]`
+    /// attribute, as these macros generate a new function.
+    #[arg(long)]
+    skip_entry_macros: bool,
 }
 
-/// The action to take.
-#[derive(Clone, ValueEnum)]
-enum Action {
-    /// Checks `tracing::instrument` is on all functions.
-    Check,
-    /// Adds `tracing::instrument` to all functions.
-    Fix,
-    /// Removes `tracing::instrument` from all functions.
-    Strip,
+/// The impl methods skipped by `--skip-boilerplate`.
+const BOILERPLATE_METHODS: [&str; 8] = [
+    "eq",
+    "ne",
+    "cmp",
+    "partial_cmp",
+    "hash",
+    "clone",
+    "fmt",
+    "drop",
+];
+
+impl CommandLineArgs {
+    /// Returns the default behaviours overridden by the explicit flags.
+    fn behavior(&self) -> Behavior {
+        let mut behavior = Behavior::new(self.compat);
+        if self.include_target {
+            behavior.skip_target = false;
+        }
+        behavior
+    }
+    /// Returns the options used when applying the action.
+    fn options(&self) -> Options {
+        let mut skip_methods = self.skip_method.clone();
+        if self.skip_boilerplate {
+            skip_methods.extend(
+                BOILERPLATE_METHODS
+                    .iter()
+                    .map(|method| String::from(*method)),
+            );
+        }
+        let mut options = Options::default();
+        options.suffix.clone_from(&self.suffix);
+        options.cfg_attr.clone_from(&self.cfg_attr);
+        options.behavior = self.behavior();
+        options.include_const = self.include_const;
+        options.trait_methods = self.trait_methods;
+        options.place_below.clone_from(&self.place_below);
+        options.enforce_level.clone_from(&self.enforce_level);
+        options.skip_cfg.clone_from(&self.skip_cfg);
+        options.warn_macro_rules = self.warn_macro_rules;
+        options.warn_track_caller = self.warn_track_caller;
+        options.warn_commented_instrument = self.warn_commented_instrument;
+        options.clean_imports = self.clean_imports;
+        options.strip_level.clone_from(&self.strip_level);
+        options.ensure_import = self.ensure_import;
+        options.skip_cfg_test = self.skip_cfg_test;
+        options.active_features.clone_from(&self.active_features);
+        options.otel_name = self.otel_name;
+        options.report_skipped = self.report_uninstrumentable;
+        options.explain = self.explain;
+        options.collect_arg_types = self.collect_args_stats;
+        options.only_free_fns = self.only_free_fns;
+        options.only_methods = self.only_methods;
+        options.max_depth = self.max_depth;
+        options.allow_fragments = self.allow_fragments;
+        options.min_branches = self.min_branches;
+        options.min_args = self.min_args;
+        options.min_args_count_self = self.min_args_count_self;
+        options.main_level.clone_from(&self.main_level);
+        options.level = Some(self.level.clone());
+        options.record_types.clone_from(&self.record_type);
+        options.record_receiver = self.record_receiver;
+        options.skip_methods = skip_methods;
+        options.skip_deprecated = self.skip_deprecated;
+        options.ret = self.ret;
+        options.tag_comment = self.tag_comment;
+        options.gap_lines = self.gap_lines;
+        options
+            .treats_as_instrumented
+            .clone_from(&self.treats_as_instrumented);
+        options.strict_instrument_path = self.strict_instrument_path;
+        options.skip_trivial = self.skip_trivial;
+        options.newline_style = self.newline_style;
+        options.require_skip = self.require_skip;
+        options.only_fns.clone_from(&self.only_fn);
+        options.root_fns.clone_from(&self.root_fn);
+        options.limit = self.limit;
+        options.refresh = self.refresh;
+        options.with_err = self.with_err;
+        options.trailing_comma = self.trailing_comma;
+        options.result_aliases.clone_from(&self.result_alias);
+        options.skip_extern_abi = self.skip_extern_abi;
+        options.skip_entry_macros = self.skip_entry_macros;
+        // `changed_lines` is set per file from `--only-changed-functions` and `confirm` from
+        // `--interactive`.
+        options
+    }
 }
 
-/// A list of text lines split so that newlines can be efficiently inserted between them.
-struct SegmentedList {
-    /// The first new line.
-    first: String,
-    /// The inner vector used to contain the original lines `.0` and the new lines `.1`.
-    inner: Vec<(String, String)>,
+/// Type to return from `main` to support returning an error then handling it.
+enum Exit {
+    /// Process completed successfully.
+    Ok,
+    /// Process encountered an error.
+    Error,
+    /// Process ran `check` action and found missing instrumentation, with the
+    /// `--missing-exit-code`.
+    Check(u8),
 }
-impl SegmentedList {
-    /// Sets the text line before `line` to `text`.
-    fn set_before(&mut self, line: usize, text: String) -> bool {
-        let s = if let Some(i) = line.checked_sub(1) {
-            let Some(mut_ref) = self.inner.get_mut(i) else {
-                return false;
-            };
-            &mut mut_ref.1
+impl std::process::Termination for Exit {
+    fn report(self) -> ExitCode {
+        match self {
+            Self::Ok => ExitCode::from(0),
+            Self::Error => ExitCode::from(1),
+            Self::Check(code) => ExitCode::from(code),
+        }
+    }
+}
+
+fn main() -> Exit {
+    let args = CommandLineArgs::parse();
+    let missing_exit_code = u8::try_from(args.missing_exit_code).unwrap_or(u8::MAX);
+    match exec(&args) {
+        Err(err) => {
+            eprintln!("Error: {err}");
+            Exit::Error
+        }
+        Ok(findings) if findings.is_empty() => Exit::Ok,
+        Ok(findings) => {
+            let max = args.max_output.unwrap_or(findings.len());
+            for finding in findings.iter().take(max) {
+                println!("{finding}");
+            }
+            if let Some(more) = findings.len().checked_sub(max).filter(|more| *more > 0) {
+                println!("... and {more} more");
+            }
+            Exit::Check(missing_exit_code)
+        }
+    }
+}
+
+/// A function with a problem found by `check`.
+struct Finding {
+    /// The path of the file containing the function.
+    path: PathBuf,
+    /// The line of the function.
+    line: usize,
+    /// The column of the function.
+    column: usize,
+    /// The identifier of the function.
+    ident: String,
+    /// The modules, impls, traits and functions enclosing the function e.g. `module::Unit`.
+    scope: String,
+    /// The problem.
+    problem: Problem,
+    /// Why the function is missing instrumentation with `--explain`.
+    explanation: Option<String>,
+    /// Whether the message names the function.
+    named: bool,
+}
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            line,
+            column,
+            ident,
+            ..
+        } = self;
+        let path = self.path.display();
+        let name = if self.named {
+            format!(" for `{ident}`")
         } else {
-            &mut self.first
+            String::new()
         };
-        *s = text;
-        true
+        match &self.problem {
+            Problem::Missing => {
+                write!(f, "Missing instrumentation{name} at {path}:{line}:{column}.")?;
+                if let Some(explanation) = &self.explanation {
+                    write!(f, " Flagged as {explanation}.")?;
+                }
+                Ok(())
+            }
+            Problem::MissingSkip => write!(
+                f,
+                "Missing `skip` or `skip_all` in instrumentation{name} at {path}:{line}:{column}."
+            ),
+            Problem::Level { found, expected } => write!(
+                f,
+                "Incorrect instrumentation level{name} at {path}:{line}:{column}, found `{found}` expected `{expected}`."
+            ),
+        }
     }
 }
-impl From<SegmentedList> for String {
-    fn from(list: SegmentedList) -> String {
-        let iter = list
-            .inner
-            .into_iter()
-            .map(|(x, y)| format!("{x}{}{y}", if y.is_empty() { "" } else { "\n" }));
-        format!(
-            "{}{}{}",
-            list.first,
-            if list.first.is_empty() { "" } else { "\n" },
-            itertools::intersperse(iter, String::from("\n")).collect::<String>()
-        )
+impl Finding {
+    /// Returns the key identifying this function and problem in a baseline file, functions with
+    /// the same identifier are distinguished by their enclosing scope e.g. `src/lib.rs:Unit::new`
+    /// and problems other than missing instrumentation by their kind e.g.
+    /// `src/lib.rs:Unit::new:level`. The path is made relative to the given canonical root.
+    fn key(&self, root: Option<&Path>) -> String {
+        let relative = root.and_then(|dir| {
+            let file = std::fs::canonicalize(&self.path).ok()?;
+            Some(file.strip_prefix(dir).ok()?.to_path_buf())
+        });
+        let path = relative.as_deref().unwrap_or(&self.path);
+        let kind = match self.problem {
+            Problem::Missing => "",
+            Problem::Level { .. } => ":level",
+            Problem::MissingSkip => ":skip",
+        };
+        if self.scope.is_empty() {
+            format!("{}:{}{kind}", path.display(), self.ident)
+        } else {
+            format!("{}:{}::{}{kind}", path.display(), self.scope, self.ident)
+        }
     }
 }
 
-/// Visitor for the `strip` action.
-struct StripVisitor(HashMap<usize, String>);
-impl From<StripVisitor> for String {
-    fn from(visitor: StripVisitor) -> String {
-        let mut vec = visitor.0.into_iter().collect::<Vec<_>>();
-        vec.sort_by_key(|(i, _)| *i);
-        itertools::intersperse(vec.into_iter().map(|(_, x)| x), String::from("\n"))
-            .collect::<String>()
-    }
+/// Error for [`exec`].
+#[derive(Debug)]
+enum ExecError {
+    /// Failed to read entry in file path.
+    Entry(walkdir::Error),
+    /// Failed to parse file path to string.
+    String,
+    /// Failed to read file.
+    File(PathBuf, std::io::Error),
+    /// Failed to run apply function.
+    Apply(PathBuf, ApplyError),
+    /// Failed to read baseline file.
+    ReadBaseline(std::io::Error),
+    /// Failed to write baseline file.
+    WriteBaseline(std::io::Error),
+    /// Failed to write report file.
+    WriteReport(std::io::Error),
+    /// Failed to write summary file.
+    WriteSummary(std::io::Error),
+    /// Failed to find a `Cargo.toml` enclosing the path.
+    Manifest(PathBuf),
+    /// Failed to run cargo.
+    Cargo(std::io::Error),
+    /// Failed to compile the package after fixing it.
+    CompileCheck(PathBuf, String),
+    /// Failed to run git.
+    Git(std::io::Error),
+    /// Failed to diff against the git ref.
+    GitDiff(String),
+    /// Warnings were emitted with `--fail-on-warnings`.
+    Warnings(usize),
+    /// No files were processed with `--error-on-empty`.
+    Empty(PathBuf),
+    /// Failed to find a `.git` directory enclosing the path for `--vcs-root`.
+    VcsRoot(PathBuf),
 }
-impl syn::visit::Visit<'_> for StripVisitor {
-    fn visit_impl_item_fn(&mut self, i: &syn::ImplItemFn) {
-        if let Some(instrument) = find_instrumented(&i.attrs) {
-            let start = instrument.span().start().line - 1;
-            let end = instrument.span().end().line;
-            for line in start..end {
-                self.0.remove(&line);
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Entry(entry) => write!(f, "Failed to read entry in file path: {entry}"),
+            Self::String => write!(f, "Failed to parse file path to string."),
+            Self::File(path, file) => {
+                write!(f, "Failed to read file {}: {file}", path.display())
             }
-        }
-        self.visit_block(&i.block);
-    }
-    fn visit_item_fn(&mut self, i: &syn::ItemFn) {
-        if let Some(instrument) = find_instrumented(&i.attrs) {
-            let start = instrument.span().start().line - 1;
-            let end = instrument.span().end().line;
-            for line in start..end {
-                self.0.remove(&line);
+            Self::Apply(path, apply) => write!(
+                f,
+                "Failed to run apply function on {}: {apply}",
+                path.display()
+            ),
+            Self::ReadBaseline(read) => write!(f, "Failed to read baseline file: {read}"),
+            Self::WriteBaseline(write) => write!(f, "Failed to write baseline file: {write}"),
+            Self::WriteReport(write) => write!(f, "Failed to write report file: {write}"),
+            Self::WriteSummary(write) => write!(f, "Failed to write summary file: {write}"),
+            Self::Manifest(path) => {
+                write!(
+                    f,
+                    "Failed to find `Cargo.toml` enclosing {}.",
+                    path.display()
+                )
+            }
+            Self::Cargo(cargo) => write!(f, "Failed to run cargo: {cargo}"),
+            Self::CompileCheck(manifest, stderr) => write!(
+                f,
+                "Failed to compile {} after fixing:\n{stderr}",
+                manifest.display()
+            ),
+            Self::Git(git) => write!(f, "Failed to run git: {git}"),
+            Self::GitDiff(stderr) => write!(f, "Failed to run git diff:\n{stderr}"),
+            Self::Warnings(warnings) => write!(f, "Emitted {warnings} warnings."),
+            Self::Empty(path) => write!(f, "Found no files to process in {}.", path.display()),
+            Self::VcsRoot(path) => {
+                write!(f, "Failed to find `.git` enclosing {}.", path.display())
             }
         }
-        self.visit_block(&i.block);
     }
 }
 
-/// Visitor for the `check` action.
-struct CheckVisitor(Option<proc_macro2::Span>);
-impl syn::visit::Visit<'_> for CheckVisitor {
-    fn visit_impl_item_fn(&mut self, i: &syn::ImplItemFn) {
-        let attr = check_attributes(&i.attrs);
-        if !attr.instrumented && !attr.skipped && !attr.test && i.sig.constness.is_none() {
-            self.0 = Some(i.span());
-        } else {
-            self.visit_block(&i.block);
+impl Error for ExecError {}
+
+/// Prints the `--print-config`, `--doctor` or `--list-recognized` information rather than
+/// processing files, returning whether any was printed.
+fn print_info(args: &CommandLineArgs) -> bool {
+    if args.print_config {
+        print!("{}", config(args));
+    } else if args.doctor {
+        print!("{}", doctor(args));
+    } else if args.list_recognized {
+        for (category, idents) in recognized_attributes(&args.options()) {
+            println!("{category}: {}", itertools::join(idents, ", "));
         }
+    } else {
+        return false;
     }
-    fn visit_item_fn(&mut self, i: &syn::ItemFn) {
-        let attr = check_attributes(&i.attrs);
-        if !attr.instrumented && !attr.skipped && !attr.test && i.sig.constness.is_none() {
-            self.0 = Some(i.span());
-        } else {
-            self.visit_block(&i.block);
+    true
+}
+
+/// Wraps functionality from `main` to support returning an error then handling it.
+fn exec(args: &CommandLineArgs) -> Result<Vec<Finding>, ExecError> {
+    if print_info(args) {
+        return Ok(Vec::new());
+    }
+    let options = args.options();
+    if args.diff_against_stdin {
+        return apply_stdin(args, &options);
+    }
+    let mut findings = Vec::new();
+    let mut totals = Totals::default();
+    let path = args.path.clone().unwrap_or(PathBuf::from("."));
+    let changed = args
+        .only_changed_functions
+        .as_ref()
+        .map(|git_ref| changed_lines(&path, git_ref))
+        .transpose()?;
+    let skip_target = options.behavior.skip_target;
+    let files = walk(&path, args, skip_target);
+    // Counting the files requires walking them twice so is only done for the progress.
+    let progress = args.progress && !args.quiet;
+    let total = if progress {
+        walk(&path, args, skip_target).flatten().count()
+    } else {
+        0
+    };
+
+    let read = |entry_path: &Path| {
+        std::fs::read(entry_path).map_err(|err| ExecError::File(entry_path.to_path_buf(), err))
+    };
+    let parse = |entry_path: &Path, buf: Vec<u8>| {
+        apply_file(
+            &args.action,
+            &options,
+            write_path(args, entry_path).as_deref(),
+            changed.as_ref(),
+            entry_path,
+            buf.as_slice(),
+        )
+    };
+    let modifies = args.action.iter().any(|action| *action != Action::Check);
+    let single = args.baseline.is_none() && args.max_output.is_none();
+    // Files are processed in order with `--limit` and `--interactive` so each depends on those
+    // before it.
+    let in_order = InOrder::default();
+    std::thread::scope(|scope| {
+        let results: Box<dyn Iterator<Item = Result<FileOutput, ExecError>>> =
+            if args.read_threads == NonZeroUsize::MIN && args.parse_threads == NonZeroUsize::MIN
+                || args.limit.is_some()
+                || args.interactive
+            {
+                Box::new(files.map(|file| {
+                    apply_in_order(args, &options, changed.as_ref(), &in_order, &file?)
+                }))
+            } else {
+                Box::new(pipeline(
+                    scope,
+                    files,
+                    args.read_threads,
+                    args.parse_threads,
+                    &read,
+                    &parse,
+                ))
+            };
+        for (index, result) in results.enumerate() {
+            let output = skip_parse_error(args, result)?;
+            totals.add(&output);
+            print_messages(args, &output);
+            if progress {
+                eprintln!("Processed {}/{total} files.", index + 1);
+            }
+            // Without a baseline or `--max-output` only the last finding in the first file is
+            // reported, later files are only processed by the actions which modify them and for
+            // the summary and statistics.
+            if !single || findings.is_empty() {
+                findings.extend(output.findings);
+            }
+            if single && !findings.is_empty() {
+                findings.drain(..findings.len() - 1);
+                if !modifies && !args.summary_json && !args.collect_args_stats && !args.by_file {
+                    break;
+                }
+            }
+            if in_order.done(args) {
+                break;
+            }
         }
+        Ok(())
+    })?;
+
+    if args.error_on_empty && totals.files == 0 {
+        return Err(ExecError::Empty(path));
+    }
+    print_totals(args, &totals)?;
+    if args.compile_check && args.action.contains(&Action::Fix) {
+        compile_check(&path)?;
     }
+    relative_to_vcs_root(args, &path, &mut findings)?;
+    let reported = report(args, &path, findings)?;
+    // Missing instrumentation already fails, so takes precedence.
+    if args.fail_on_warnings && totals.warnings > 0 && reported.is_empty() {
+        return Err(ExecError::Warnings(totals.warnings));
+    }
+    Ok(reported)
 }
 
-/// Visitor for the `fix` action.
-struct FixVisitor<'a> {
-    /// A custom path suffix.
-    suffix: &'a Option<String>,
-    /// A `cfg_attr` condition.
-    cfg_attr: &'a Option<String>,
-    /// Source
-    list: SegmentedList,
-}
-impl From<FixVisitor<'_>> for String {
-    fn from(visitor: FixVisitor) -> String {
-        String::from(visitor.list)
+/// Returns a file which failed to parse as processed with only a warning with
+/// `--skip-parse-errors`, so it neither fails nor finds missing instrumentation.
+fn skip_parse_error(
+    args: &CommandLineArgs,
+    result: Result<FileOutput, ExecError>,
+) -> Result<FileOutput, ExecError> {
+    match result {
+        Err(ExecError::Apply(path, err @ (ApplyError::Syn(_) | ApplyError::Depth(_))))
+            if args.skip_parse_errors =>
+        {
+            Ok(FileOutput {
+                warnings: vec![format!("Warning: Skipped {}: {err}", path.display())],
+                path,
+                findings: Vec::new(),
+                skipped: Vec::new(),
+                arg_types: Vec::new(),
+                removed: 0,
+                functions: 0,
+                covered: 0,
+                instrumented: 0,
+            })
+        }
+        _ => result,
     }
 }
 
-impl syn::visit::Visit<'_> for FixVisitor<'_> {
-    fn visit_impl_item_fn(&mut self, i: &syn::ImplItemFn) {
-        let attr = check_attributes(&i.attrs);
-        if !attr.instrumented && !attr.skipped && !attr.test && i.sig.constness.is_none() {
-            let line = i.span().start().line;
+/// Prints the warnings and `--report-uninstrumentable` skips of a file.
+fn print_messages(args: &CommandLineArgs, output: &FileOutput) {
+    if !args.quiet {
+        for warning in &output.warnings {
+            eprintln!("{warning}");
+        }
+    }
+    for skipped in &output.skipped {
+        println!("{skipped}");
+    }
+}
 
-            let attr_string = instrument(&i.sig, self.suffix, self.cfg_attr);
-            let indent = i.span().start().column;
-            let indent_attr = format!("{}{attr_string}", " ".repeat(indent));
-            self.list.set_before(line - 1, indent_attr);
+/// Prints the `--dry-run` removals and the `--summary-json` summary.
+fn print_totals(args: &CommandLineArgs, totals: &Totals) -> Result<(), ExecError> {
+    if args.dry_run && args.action.contains(&Action::Strip) {
+        println!(
+            "Would remove {} instrument attributes from {} files.",
+            totals.removed, totals.removed_files
+        );
+    }
+    if args.summary_json && args.action.contains(&Action::Check) {
+        if let Some(summary_file) = &args.summary_file {
+            std::fs::write(summary_file, totals.summary_json()).map_err(ExecError::WriteSummary)?;
+        } else {
+            eprint!("{}", totals.summary_json());
+        }
+    }
+    if args.by_file && args.action.contains(&Action::Check) {
+        let mut by_file = totals.by_file.iter().collect::<Vec<_>>();
+        // Ties are in path order so the output is stable.
+        by_file.sort_by(|(a_path, _, a), (b_path, _, b)| b.cmp(a).then_with(|| a_path.cmp(b_path)));
+        for (path, covered, missing) in by_file {
+            println!(
+                "{}: {covered} instrumented, {missing} missing",
+                path.display()
+            );
+        }
+    }
+    if args.limit.is_some() && args.action.contains(&Action::Fix) {
+        println!("Instrumented {} functions.", totals.instrumented);
+    }
+    if args.collect_args_stats {
+        let mut counts = totals.arg_types.iter().collect::<Vec<_>>();
+        // Most common first, ties are in alphabetical order as the map is sorted.
+        counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+        for (arg_type, count) in counts {
+            println!("{count} {arg_type}");
         }
-        self.visit_block(&i.block);
     }
-    fn visit_item_fn(&mut self, i: &syn::ItemFn) {
-        let attr = check_attributes(&i.attrs);
+    Ok(())
+}
 
-        if !attr.instrumented && !attr.skipped && !attr.test && i.sig.constness.is_none() {
-            let line = i.span().start().line;
+/// Filters the findings by the `--baseline` and writes them to the `--report-file`.
+///
+/// Without a `--vcs-root` the baseline keys are relative to the given `--path`, or the directory
+/// containing it when it is a file.
+fn report(
+    args: &CommandLineArgs,
+    path: &Path,
+    mut findings: Vec<Finding>,
+) -> Result<Vec<Finding>, ExecError> {
+    if let Some(baseline) = &args.baseline {
+        let vcs_root = args.vcs_root.is_some() || args.detect_vcs_root;
+        let dir = if path.is_dir() {
+            path
+        } else {
+            path.parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .unwrap_or(Path::new("."))
+        };
+        let root = (!vcs_root)
+            .then(|| std::fs::canonicalize(dir).ok())
+            .flatten();
+        let key = |finding: &Finding| finding.key(root.as_deref());
+        if args.write_baseline {
+            let keys = findings.iter().map(|f| format!("{}\n", key(f)));
+            std::fs::write(baseline, keys.collect::<String>()).map_err(ExecError::WriteBaseline)?;
+            return Ok(Vec::new());
+        }
+        let text = std::fs::read_to_string(baseline).map_err(ExecError::ReadBaseline)?;
+        let known = text.lines().collect::<std::collections::HashSet<_>>();
+        findings.retain(|f| !known.contains(key(f).as_str()));
+    }
+    if let Some(report_file) = &args.report_file {
+        let report = findings.iter().map(|f| format!("{f}\n"));
+        std::fs::write(report_file, report.collect::<String>()).map_err(ExecError::WriteReport)?;
+    }
+    Ok(findings)
+}
 
-            let attr_string = instrument(&i.sig, self.suffix, self.cfg_attr);
-            let indent = i.span().start().column;
-            let indent_attr = format!("{}{attr_string}", " ".repeat(indent));
-            self.list.set_before(line - 1, indent_attr);
+/// Makes the paths of the given findings relative to the `--vcs-root`, found from the given path
+/// with `--detect-vcs-root`. Paths outside the root are unchanged.
+fn relative_to_vcs_root(
+    args: &CommandLineArgs,
+    path: &Path,
+    findings: &mut [Finding],
+) -> Result<(), ExecError> {
+    let canonical = |dir: &Path| {
+        std::fs::canonicalize(dir).map_err(|err| ExecError::File(dir.to_path_buf(), err))
+    };
+    let root = if let Some(root) = &args.vcs_root {
+        canonical(root)?
+    } else if args.detect_vcs_root {
+        canonical(path)?
+            .ancestors()
+            .find(|dir| dir.join(".git").exists())
+            .ok_or_else(|| ExecError::VcsRoot(path.to_path_buf()))?
+            .to_path_buf()
+    } else {
+        return Ok(());
+    };
+    for finding in findings {
+        let file = canonical(&finding.path)?;
+        if let Ok(relative) = file.strip_prefix(&root) {
+            finding.path = relative.to_path_buf();
         }
-        self.visit_block(&i.block);
     }
+    Ok(())
 }
 
-fn instrument(sig: &syn::Signature, suffix: &Option<String>, cfg_attr: &Option<String>) -> String {
-    let instr = inner_instrument(sig, suffix);
-    if let Some(cfg_attr) = cfg_attr {
-        format!("#[cfg_attr({cfg_attr}, {instr})]")
+/// Runs `cargo check` on the package enclosing the given path.
+fn compile_check(path: &Path) -> Result<(), ExecError> {
+    let absolute =
+        std::fs::canonicalize(path).map_err(|err| ExecError::File(path.to_path_buf(), err))?;
+    let manifest = absolute
+        .ancestors()
+        .map(|dir| dir.join("Cargo.toml"))
+        .find(|manifest| manifest.is_file())
+        .ok_or_else(|| ExecError::Manifest(path.to_path_buf()))?;
+    // Prefer the cargo running this tool e.g. from `cargo run`.
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let output = std::process::Command::new(cargo)
+        .arg("check")
+        .arg("--manifest-path")
+        .arg(&manifest)
+        .output()
+        .map_err(ExecError::Cargo)?;
+    if output.status.success() {
+        Ok(())
     } else {
-        format!("#[{instr}]")
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        Err(ExecError::CompileCheck(manifest, stderr))
     }
 }
 
-/// Returns the instrument macro for a given function signature.
-#[cfg(not(feature = "log"))]
-fn inner_instrument(sig: &syn::Signature, suffix: &Option<String>) -> String {
-    let iter = sig.inputs.iter().flat_map(|arg| match arg {
-        syn::FnArg::Receiver(_) => vec![String::from("self")],
-        syn::FnArg::Typed(syn::PatType { pat, .. }) => match &**pat {
-            syn::Pat::Ident(syn::PatIdent { ident, .. }) => vec![ident.to_string()],
-            syn::Pat::Struct(syn::PatStruct { fields, .. }) => fields
-                .iter()
-                .filter_map(|f| match &f.member {
-                    syn::Member::Named(ident) => Some(ident.to_string()),
-                    syn::Member::Unnamed(_) => None,
-                })
-                .collect::<Vec<_>>(),
-            _ => Vec::new(),
-        },
-    });
-    let args = itertools::intersperse(iter, String::from(", ")).collect::<String>();
+/// The ranges of lines changed in each file, keyed by canonical path.
+type ChangedLines = BTreeMap<PathBuf, Vec<Range<usize>>>;
+
+/// Returns the lines changed since the given git ref in the files within the given path.
+fn changed_lines(path: &Path, git_ref: &str) -> Result<ChangedLines, ExecError> {
+    let dir = if path.is_dir() {
+        path
+    } else {
+        path.parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+    };
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args([
+            "diff",
+            "-U0",
+            "--no-color",
+            "--no-prefix",
+            "--relative",
+            git_ref,
+        ])
+        .output()
+        .map_err(ExecError::Git)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        return Err(ExecError::GitDiff(stderr));
+    }
+
+    let mut changed = ChangedLines::new();
+    let mut file = None;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some(name) = line.strip_prefix("+++ ") {
+            // Deleted files have no new lines.
+            file = (name != "/dev/null")
+                .then(|| dir.join(name).canonicalize().ok())
+                .flatten();
+            continue;
+        }
+        let hunk = line.strip_prefix("@@ ").and_then(hunk_range);
+        if let (Some(range), Some(name)) = (hunk, &file) {
+            changed.entry(name.clone()).or_default().push(range);
+        }
+    }
+    Ok(changed)
+}
+
+/// Returns the range of new lines in the given hunk header following `@@ `.
+///
+/// A hunk header is `@@ -{start},{len} +{start},{len} @@`, where a missing length is 1 and a
+/// length of 0 is a deletion after the start, which is treated as changing the start.
+fn hunk_range(hunk: &str) -> Option<Range<usize>> {
+    let new = hunk.split(' ').find_map(|range| range.strip_prefix('+'))?;
+    let (start, len) = new.split_once(',').unwrap_or((new, "1"));
+    let first = start.parse::<usize>().ok()?;
+    let count = len.parse::<usize>().ok()?;
+    Some(first..first + count.max(1))
+}
+
+/// Returns the diagnostics printed by `--doctor`.
+fn doctor(args: &CommandLineArgs) -> String {
+    let rustfmt = match std::process::Command::new("rustfmt")
+        .arg("--version")
+        .output()
+    {
+        Ok(output) if output.status.success() => {
+            format!("found, {}", String::from_utf8_lossy(&output.stdout).trim())
+        }
+        _ => String::from("not found"),
+    };
+    let path = args.path.as_deref().unwrap_or(Path::new("."));
+    let exists = if path.exists() {
+        "exists"
+    } else {
+        "does not exist"
+    };
     format!(
-        "{}instrument(level = \"trace\", skip({args}))",
-        suffix.as_ref().map_or("tracing::", String::as_str)
+        "version: {}\nrustfmt: {rustfmt}\npath: {} {exists}\n\n{}",
+        env!("CARGO_PKG_VERSION"),
+        path.display(),
+        config(args)
     )
 }
 
-/// Returns the instrument macro for a given function signature.
-#[cfg(feature = "log")]
-fn inner_instrument(_sig: &syn::Signature, suffix: &Option<String>) -> String {
+/// Returns the effective configuration as TOML.
+fn config(args: &CommandLineArgs) -> String {
+    let mut lines = Vec::new();
+    let actions = args
+        .action
+        .iter()
+        .filter_map(|action| value_name(action, ActionArg::action))
+        .collect::<Vec<_>>();
+    // A single action is written as a string.
+    match actions.as_slice() {
+        [action] => lines.push(format!("action = {action:?}")),
+        _ => lines.push(format!("action = {}", array(&actions))),
+    }
+    let path = args.path.as_deref().unwrap_or(Path::new("."));
+    lines.push(format!("path = {:?}", path.display().to_string()));
+    if let Some(suffix) = &args.suffix {
+        lines.push(format!("suffix = {suffix:?}"));
+    }
+    if let Some(cfg_attr) = &args.cfg_attr {
+        lines.push(format!("cfg-attr = {cfg_attr:?}"));
+    }
+    lines.push(format!("exclude = {}", array(&args.exclude)));
+    if let Some(since) = &args.modified_since {
+        lines.push(format!("modified-since = {:?}", since.text));
+    }
+    if let Some(compat) = &args.compat {
+        lines.push(format!("compat = \"{compat}\""));
+    }
+    lines.extend(report_config(args));
+    lines.push(format!("place-below = {}", array(&args.place_below)));
+    lines.push(format!(
+        "treats-as-instrumented = {}",
+        array(&args.treats_as_instrumented)
+    ));
+    lines.push(format!(
+        "strict-instrument-path = {}",
+        args.strict_instrument_path
+    ));
+    if let Some(level) = &args.enforce_level {
+        lines.push(format!("enforce-level = {level:?}"));
+    }
+    lines.push(format!("require-skip = {}", args.require_skip));
+    lines.push(format!("collect-args-stats = {}", args.collect_args_stats));
+    lines.push(format!(
+        "report-uninstrumentable = {}",
+        args.report_uninstrumentable
+    ));
+    lines.push(format!("explain = {}", args.explain));
+    lines.push(format!("level = {:?}", args.level));
+    if let Some(level) = &args.main_level {
+        lines.push(format!("main-level = {level:?}"));
+    }
+    lines.push(format!("read-threads = {}", args.read_threads));
+    lines.push(format!("parse-threads = {}", args.parse_threads));
+    lines.extend(selection_config(args));
+    lines.extend(walk_config(args));
+    lines.extend(generation_config(args));
+    if let Some(style) = value_name(&args.newline_style, NewlineStyleArg::style) {
+        lines.push(format!("newline-style = {style:?}"));
+    }
+    if let Some(git_ref) = &args.only_changed_functions {
+        lines.push(format!("only-changed-functions = {git_ref:?}"));
+    }
+    lines.push(format!("warn-macro-rules = {}", args.warn_macro_rules));
+    lines.push(format!("warn-track-caller = {}", args.warn_track_caller));
+    lines.push(format!(
+        "warn-commented-instrument = {}",
+        args.warn_commented_instrument
+    ));
+    lines.push(format!("fail-on-warnings = {}", args.fail_on_warnings));
+    lines.push(format!("error-on-empty = {}", args.error_on_empty));
+    lines.extend(modification_config(args));
+    lines.push(format!("progress = {}", args.progress));
+    lines.push(format!("quiet = {}", args.quiet));
+    lines.push(format!("dry-run = {}", args.dry_run));
+    if let Some(output_dir) = &args.output_dir {
+        lines.push(format!(
+            "output-dir = {:?}",
+            output_dir.display().to_string()
+        ));
+    }
+    lines.push(format!("doctor = {}", args.doctor));
+    lines.push(format!("list-recognized = {}", args.list_recognized));
+    lines.push(format!("diff-against-stdin = {}", args.diff_against_stdin));
+    lines.push(format!("interactive = {}", args.interactive));
+    lines.extend(parse_config(args));
+    lines.push(format!("missing-exit-code = {}", args.missing_exit_code));
+    if let Some(max_output) = args.max_output {
+        lines.push(format!("max-output = {max_output}"));
+    }
+    if let Some(limit) = args.limit {
+        lines.push(format!("limit = {limit}"));
+    }
+    lines.push(format!("include-const = {}", args.include_const));
+    lines.push(format!("trait-methods = {}", args.trait_methods));
+    let behavior = args.behavior();
+    lines.push(String::from("\n[behavior]"));
+    lines.push(format!("skip-target = {}", behavior.skip_target));
+    lines.push(format!("named-messages = {}", behavior.named_messages));
+    lines.push(format!("check-nested = {}", behavior.check_nested));
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// Formats a list of strings as a TOML array.
+fn array(values: &[String]) -> String {
     format!(
-        "{}instrument",
-        suffix.as_ref().map_or("log_instrument::", String::as_str)
+        "[{}]",
+        itertools::join(values.iter().map(|value| format!("{value:?}")), ", ")
     )
 }
 
-/// Type to return from `main` to support returning an error then handling it.
-#[repr(u8)]
-enum Exit {
-    /// Process completed successfully.
-    Ok = 0,
-    /// Process encountered an error.
-    Error = 1,
-    /// Process ran `check` action and found missing instrumentation.
-    Check = 2,
+/// Returns the configuration of the generated instrumentation as TOML lines.
+fn generation_config(args: &CommandLineArgs) -> Vec<String> {
+    let mut lines = Vec::new();
+    lines.push(format!("ret = {}", args.ret));
+    lines.push(format!("otel-name = {}", args.otel_name));
+    lines.push(format!("root-fn = {}", array(&args.root_fn)));
+    lines.push(format!("with-err = {}", args.with_err));
+    lines.push(format!("trailing-comma = {}", args.trailing_comma));
+    lines.push(format!("result-alias = {}", array(&args.result_alias)));
+    lines.push(format!("tag-comment = {}", args.tag_comment));
+    lines.push(format!("gap-lines = {}", args.gap_lines));
+    lines.push(format!("refresh = {}", args.refresh));
+    lines
 }
-#[allow(clippy::as_conversions)]
-impl std::process::Termination for Exit {
-    fn report(self) -> ExitCode {
-        ExitCode::from(self as u8)
+
+/// Returns the configuration of the changes made besides instrumentation as TOML lines.
+fn modification_config(args: &CommandLineArgs) -> Vec<String> {
+    let mut lines = Vec::new();
+    lines.push(format!("clean-imports = {}", args.clean_imports));
+    if let Some(level) = &args.strip_level {
+        lines.push(format!("strip-level = {level:?}"));
     }
+    lines.push(format!("ensure-import = {}", args.ensure_import));
+    lines.push(format!("compile-check = {}", args.compile_check));
+    lines
 }
 
-fn main() -> Exit {
-    match exec() {
-        Err(err) => {
-            eprintln!("Error: {err}");
-            Exit::Error
-        }
-        Ok(None) => Exit::Ok,
-        Ok(Some((path, line, column))) => {
-            println!(
-                "Missing instrumentation at {}:{line}:{column}.",
-                path.display()
-            );
-            Exit::Check
-        }
+/// Returns the configuration of which files are walked as TOML lines.
+fn walk_config(args: &CommandLineArgs) -> Vec<String> {
+    let mut lines = Vec::new();
+    lines.push(format!("markdown = {}", args.markdown));
+    lines.push(format!("skip-out-dir = {}", args.skip_out_dir));
+    lines.push(format!("include-build-rs = {}", args.include_build_rs));
+    if let Some(exclude_marker) = &args.exclude_marker {
+        lines.push(format!("exclude-marker = {exclude_marker:?}"));
     }
+    lines
 }
 
-/// Error for [`exec`].
-#[derive(Debug)]
-enum ExecError {
-    /// Failed to read entry in file path.
-    Entry(walkdir::Error),
-    /// Failed to parse file path to string.
-    String,
-    /// Failed to open file.
-    File(std::io::Error),
-    /// Failed to run apply function.
-    Apply(ApplyError),
+/// Returns the configuration of which functions require instrumentation as TOML lines.
+fn selection_config(args: &CommandLineArgs) -> Vec<String> {
+    let mut lines = Vec::new();
+    lines.push(format!("skip-cfg = {}", array(&args.skip_cfg)));
+    lines.push(format!("skip-cfg-test = {}", args.skip_cfg_test));
+    if let Some(active_features) = &args.active_features {
+        lines.push(format!("active-features = {}", array(active_features)));
+    }
+    lines.push(format!("record-type = {}", array(&args.record_type)));
+    lines.push(format!("record-receiver = {}", args.record_receiver));
+    lines.push(format!("skip-method = {}", array(&args.skip_method)));
+    lines.push(format!("only-fn = {}", array(&args.only_fn)));
+    lines.push(format!("only-free-fns = {}", args.only_free_fns));
+    lines.push(format!("only-methods = {}", args.only_methods));
+    lines.push(format!("skip-boilerplate = {}", args.skip_boilerplate));
+    lines.push(format!("skip-deprecated = {}", args.skip_deprecated));
+    lines.push(format!("skip-trivial = {}", args.skip_trivial));
+    lines.push(format!("skip-extern-abi = {}", args.skip_extern_abi));
+    lines.push(format!("skip-entry-macros = {}", args.skip_entry_macros));
+    if let Some(min_branches) = args.min_branches {
+        lines.push(format!("min-branches = {min_branches}"));
+    }
+    if let Some(min_args) = args.min_args {
+        lines.push(format!("min-args = {min_args}"));
+        lines.push(format!(
+            "min-args-count-self = {}",
+            args.min_args_count_self
+        ));
+    }
+    lines
 }
-impl fmt::Display for ExecError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::Entry(entry) => write!(f, "Failed to read entry in file path: {entry}"),
-            Self::String => write!(f, "Failed to parse file path to string."),
-            Self::File(file) => write!(f, "Failed to open file: {file}"),
-            Self::Apply(apply) => write!(f, "Failed to run apply function: {apply}"),
-        }
+
+/// Returns the configuration of how files are parsed as TOML lines.
+fn parse_config(args: &CommandLineArgs) -> Vec<String> {
+    let mut lines = Vec::new();
+    lines.push(format!("allow-fragments = {}", args.allow_fragments));
+    lines.push(format!("skip-parse-errors = {}", args.skip_parse_errors));
+    if let Some(max_depth) = args.max_depth {
+        lines.push(format!("max-depth = {max_depth}"));
     }
+    lines
 }
 
-impl Error for ExecError {}
+/// Returns the configuration of the files `check` reads and writes as TOML lines.
+fn report_config(args: &CommandLineArgs) -> Vec<String> {
+    let mut lines = Vec::new();
+    if let Some(baseline) = &args.baseline {
+        lines.push(format!("baseline = {:?}", baseline.display().to_string()));
+    }
+    if let Some(report_file) = &args.report_file {
+        lines.push(format!(
+            "report-file = {:?}",
+            report_file.display().to_string()
+        ));
+    }
+    if let Some(root) = &args.vcs_root {
+        lines.push(format!("vcs-root = {:?}", root.display().to_string()));
+    }
+    lines.push(format!("detect-vcs-root = {}", args.detect_vcs_root));
+    lines.push(format!("summary-json = {}", args.summary_json));
+    lines.push(format!("by-file = {}", args.by_file));
+    if let Some(summary_file) = &args.summary_file {
+        lines.push(format!(
+            "summary-file = {:?}",
+            summary_file.display().to_string()
+        ));
+    }
+    lines.push(format!("write-baseline = {}", args.write_baseline));
+    lines
+}
 
-/// Wraps functionality from `main` to support returning an error then handling it.
-fn exec() -> Result<Option<(PathBuf, usize, usize)>, ExecError> {
-    let args = CommandLineArgs::parse();
+/// Returns the paths of the files to process within the given path, filtered by the `--exclude`,
+/// `--markdown` and `--modified-since` arguments.
+fn walk<'a>(
+    path: &Path,
+    args: &'a CommandLineArgs,
+    skip_target: bool,
+) -> impl Iterator<Item = Result<PathBuf, ExecError>> + 'a {
+    let exclude = &args.exclude;
+    let markdown = args.markdown;
+    let include_build_rs = args.include_build_rs;
+    let since = args.modified_since.as_ref().map(|since| since.time);
+    let out_dir = args
+        .skip_out_dir
+        .then(|| std::env::var_os("OUT_DIR"))
+        .flatten()
+        .and_then(|dir| Path::new(&dir).canonicalize().ok());
+    let skip_out_dir = args.skip_out_dir;
+    let marker = args.exclude_marker.as_deref();
+    let output_dir = args.output_dir.as_deref();
+    WalkDir::new(path)
+        .follow_links(true)
+        .into_iter()
+        .filter_entry(move |entry| {
+            !((skip_target && skipped_dir(entry))
+                || (skip_out_dir && generated_dir(entry, out_dir.as_deref()))
+                || marker.is_some_and(|name| marked_dir(entry, name))
+                || output_dir.is_some_and(|dir| same_dir(entry, dir)))
+        })
+        .filter_map(move |entry| included(exclude, markdown, include_build_rs, entry))
+        .filter(move |file| match (file, since) {
+            (Ok(file_path), Some(time)) => modified_since(file_path, time),
+            _ => true,
+        })
+}
 
-    let path = args.path.unwrap_or(PathBuf::from("."));
-    for entry_res in WalkDir::new(path).follow_links(true) {
-        let entry = entry_res.map_err(ExecError::Entry)?;
-        let entry_path = entry.into_path();
-
-        let path_str = entry_path.to_str().ok_or(ExecError::String)?;
-        // File paths must not contain any excluded strings.
-        let a = !args.exclude.iter().any(|e| path_str.contains(e));
-        // The file must not be a `build.rs` file.
-        let b = !entry_path.ends_with("build.rs");
-        // The file must be a `.rs` file.
-        let c = entry_path.extension().map_or(false, |ext| ext == "rs");
-
-        if a && b && c {
-            let file = OpenOptions::new()
-                .read(true)
-                .open(&entry_path)
-                .map_err(ExecError::File)?;
-            let res = apply(&args.action, &args.suffix, &args.cfg_attr, file, |_| {
-                OpenOptions::new()
-                    .write(true)
-                    .truncate(true)
-                    .open(&entry_path)
-            })
-            .map_err(ExecError::Apply)?;
+/// Returns whether the file at the given path was modified at or after the given time, files
+/// without a modification time are included so failing to read them is reported.
+fn modified_since(path: &Path, time: SystemTime) -> bool {
+    match std::fs::metadata(path).and_then(|metadata| metadata.modified()) {
+        Ok(modified) => modified >= time,
+        Err(_) => true,
+    }
+}
 
-            if let Some(span) = res {
-                return Ok(Some((entry_path, span.start().line, span.start().column)));
-            }
+/// The values of `--action`.
+#[derive(Clone, Copy, ValueEnum)]
+enum ActionArg {
+    /// Checks `tracing::instrument` is on all functions.
+    Check,
+    /// Adds `tracing::instrument` to all functions.
+    Fix,
+    /// Removes `tracing::instrument` from all functions.
+    Strip,
+    /// Removes all but the first `tracing::instrument` from functions with multiple.
+    Dedupe,
+}
+impl ActionArg {
+    /// Returns the action.
+    const fn action(self) -> Action {
+        match self {
+            Self::Check => Action::Check,
+            Self::Fix => Action::Fix,
+            Self::Strip => Action::Strip,
+            Self::Dedupe => Action::Dedupe,
+        }
+    }
+}
+
+/// The values of `--newline-style`.
+#[derive(Clone, Copy, ValueEnum)]
+enum NewlineStyleArg {
+    /// Keeps the line ending of each original line, inserted lines use the line ending of the
+    /// line after them.
+    Auto,
+    /// Uses `\n`.
+    Lf,
+    /// Uses `\r\n`.
+    Crlf,
+}
+impl NewlineStyleArg {
+    /// Returns the newline style.
+    const fn style(self) -> NewlineStyle {
+        match self {
+            Self::Auto => NewlineStyle::Auto,
+            Self::Lf => NewlineStyle::Lf,
+            Self::Crlf => NewlineStyle::Crlf,
         }
     }
-    Ok(None)
 }
 
-/// Error for [`apply`].
+/// Returns the command line name of the given value, the argument value converted to it by
+/// `convert`.
+fn value_name<A: ValueEnum + Copy, T: PartialEq>(value: &T, convert: fn(A) -> T) -> Option<String> {
+    A::value_variants()
+        .iter()
+        .find(|arg| convert(**arg) == *value)
+        .and_then(ValueEnum::to_possible_value)
+        .map(|arg| String::from(arg.get_name()))
+}
+
+/// The time of `--modified-since`.
+#[derive(Clone)]
+struct Since {
+    /// The value as given.
+    text: String,
+    /// The time.
+    time: SystemTime,
+}
+impl core::str::FromStr for Since {
+    type Err = SinceError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let time = if let Some(secs) = duration_secs(s) {
+            SystemTime::now().checked_sub(Duration::from_secs(secs))
+        } else {
+            let secs = rfc3339_secs(s).ok_or(SinceError::Format)?;
+            SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+        };
+        Ok(Self {
+            text: String::from(s),
+            time: time.ok_or(SinceError::Range)?,
+        })
+    }
+}
+
+/// Error for parsing a [`Since`].
 #[derive(Debug)]
-enum ApplyError {
-    /// Failed to read file.
-    Read(std::io::Error),
-    /// Failed to parse file to utf8.
-    Utf(core::str::Utf8Error),
-    /// Failed to parse file to syn ast.
-    Syn(syn::parse::Error),
-    /// Failed to get write target.
-    Target(std::io::Error),
-    /// Failed to write result to target.
-    Write(std::io::Error),
-}
-impl fmt::Display for ApplyError {
+enum SinceError {
+    /// The value is neither an RFC 3339 timestamp nor a duration.
+    Format,
+    /// The time is before the Unix epoch or not representable.
+    Range,
+}
+impl fmt::Display for SinceError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Read(read) => write!(f, "Failed to read file: {read}"),
-            Self::Utf(utf) => write!(f, "Failed to parse file to utf8: {utf}"),
-            Self::Syn(syn) => write!(f, "Failed to parse file to syn ast: {syn}"),
-            Self::Target(target) => write!(f, "Failed to get write target: {target}"),
-            Self::Write(write) => write!(f, "Failed to write result to target: {write}"),
+            Self::Format => write!(
+                f,
+                "Expected an RFC 3339 timestamp e.g. `2024-01-31T12:00:00Z` or a duration e.g. `12h`."
+            ),
+            Self::Range => write!(f, "Time is out of range."),
         }
     }
 }
 
-impl Error for ApplyError {}
+impl Error for SinceError {}
 
-/// Apply the given action to the given source and outputs the result to the target produced by the
-/// given closure.
-fn apply<R: Read, W: Write>(
-    action: &Action,
-    suffix: &Option<String>,
-    cfg_attr: &Option<String>,
-    mut source: R,
-    target: impl Fn(R) -> Result<W, std::io::Error>,
-) -> Result<Option<proc_macro2::Span>, ApplyError> {
-    let mut buf = Vec::new();
-    source.read_to_end(&mut buf).map_err(ApplyError::Read)?;
-    let text = core::str::from_utf8(&buf).map_err(ApplyError::Utf)?;
+/// Returns the number of seconds in a duration e.g. `90s`, `30m`, `12h`, `7d` or `2w`.
+fn duration_secs(s: &str) -> Option<u64> {
+    let unit = s.chars().last()?;
+    let multiplier: u64 = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3_600,
+        'd' => 86_400,
+        'w' => 604_800,
+        _ => return None,
+    };
+    let count = s.strip_suffix(unit)?.parse::<u64>().ok()?;
+    count.checked_mul(multiplier)
+}
 
-    let ast = syn::parse_file(text).map_err(ApplyError::Syn)?;
+/// Returns the seconds since the Unix epoch of an RFC 3339 timestamp e.g.
+/// `2024-01-31T12:00:00Z` or `2024-01-31T12:00:00.5+01:00`, fractional seconds are ignored.
+fn rfc3339_secs(s: &str) -> Option<u64> {
+    /// Parses exactly 3 integers separated by the given separator.
+    fn triple(s: &str, separator: char) -> Option<(i64, i64, i64)> {
+        let mut parts = s.split(separator).map(str::parse::<i64>);
+        let triple = (
+            parts.next()?.ok()?,
+            parts.next()?.ok()?,
+            parts.next()?.ok()?,
+        );
+        parts.next().is_none().then_some(triple)
+    }
 
-    match action {
-        Action::Strip => {
-            let mut visitor = StripVisitor(
-                text.split('\n')
-                    .enumerate()
-                    .map(|(i, x)| (i, String::from(x)))
-                    .collect(),
-            );
-            visitor.visit_file(&ast);
-            let out = String::from(visitor);
-            target(source)
-                .map_err(ApplyError::Target)?
-                .write_all(out.as_bytes())
-                .map_err(ApplyError::Write)?;
-            Ok(None)
+    let (date, time_offset) = s.split_once(['T', 't', ' '])?;
+    let (year, month, day) = triple(date, '-')?;
+    let (time, offset) = if let Some(utc) = time_offset.strip_suffix(['Z', 'z']) {
+        (utc, 0)
+    } else {
+        let (local, offset) = time_offset.split_at(time_offset.rfind(['+', '-'])?);
+        let sign: i64 = if offset.starts_with('-') { -1 } else { 1 };
+        let (hours, minutes) = offset.get(1..)?.split_once(':')?;
+        let secs = hours.parse::<i64>().ok()? * 3_600 + minutes.parse::<i64>().ok()? * 60;
+        (local, sign * secs)
+    };
+    let (hour, minute, second) = triple(time.split('.').next()?, ':')?;
+    let valid = (1..=12).contains(&month)
+        && (1..=31).contains(&day)
+        && (0..24).contains(&hour)
+        && (0..60).contains(&minute)
+        && (0..=60).contains(&second);
+    if !valid {
+        return None;
+    }
+    // The days since the epoch of the date, see
+    // https://howardhinnant.github.io/date_algorithms.html#days_from_civil.
+    let march_year = if month <= 2 { year - 1 } else { year };
+    let era = march_year.div_euclid(400);
+    let year_of_era = march_year.rem_euclid(400);
+    let day_of_year = (153 * (month + 9).rem_euclid(12) + 2).div_euclid(5) + day - 1;
+    let day_of_era =
+        year_of_era * 365 + year_of_era.div_euclid(4) - year_of_era.div_euclid(100) + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    u64::try_from(days * 86_400 + hour * 3_600 + minute * 60 + second - offset).ok()
+}
+
+/// Returns whether the given entry is a `target` or `.git` directory below the root path.
+fn skipped_dir(entry: &walkdir::DirEntry) -> bool {
+    entry.depth() > 0
+        && entry.file_type().is_dir()
+        && (entry.file_name() == "target" || entry.file_name() == ".git")
+}
+
+/// Returns whether the given entry is the given directory, such as the `--output-dir` within the
+/// `--path` tree.
+///
+/// The directory is canonicalized for each entry as `--output-dir` is created during the walk.
+fn same_dir(entry: &walkdir::DirEntry, dir: &Path) -> bool {
+    entry.file_type().is_dir()
+        && entry
+            .path()
+            .canonicalize()
+            .is_ok_and(|canonical| dir.canonicalize().is_ok_and(|other| canonical == other))
+}
+
+/// Returns whether the given entry is an `out` directory below a `target` directory or the given
+/// `OUT_DIR`.
+fn generated_dir(entry: &walkdir::DirEntry, out_dir: Option<&Path>) -> bool {
+    entry.file_type().is_dir()
+        && ((entry.file_name() == "out"
+            && entry
+                .path()
+                .ancestors()
+                .skip(1)
+                .any(|ancestor| ancestor.file_name().is_some_and(|name| name == "target")))
+            || out_dir.is_some_and(|dir| {
+                entry
+                    .path()
+                    .canonicalize()
+                    .is_ok_and(|canonical| canonical == dir)
+            }))
+}
+
+/// Returns whether the given entry is a directory containing a file with the given name.
+fn marked_dir(entry: &walkdir::DirEntry, name: &str) -> bool {
+    entry.file_type().is_dir() && entry.path().join(name).is_file()
+}
+
+/// Returns the path of the given entry if it should be processed.
+fn included(
+    exclude: &[String],
+    markdown: bool,
+    include_build_rs: bool,
+    entry_res: Result<walkdir::DirEntry, walkdir::Error>,
+) -> Option<Result<PathBuf, ExecError>> {
+    let entry_path = match entry_res {
+        Ok(entry) => entry.into_path(),
+        Err(err) => return Some(Err(ExecError::Entry(err))),
+    };
+
+    let Some(path_str) = entry_path.to_str() else {
+        return Some(Err(ExecError::String));
+    };
+    // File paths must not contain any excluded strings, compared with `/` separators so
+    // excludes are portable.
+    let normalized = path_str.replace(std::path::MAIN_SEPARATOR, "/");
+    let a = !exclude
+        .iter()
+        .any(|e| normalized.contains(&e.replace(std::path::MAIN_SEPARATOR, "/")));
+    // The file must not be a `build.rs` file, unless `--include-build-rs`.
+    let b = include_build_rs || !entry_path.ends_with("build.rs");
+    // The file must be a `.rs` file, or a `.md` file with `--markdown`.
+    let c = entry_path
+        .extension()
+        .is_some_and(|ext| ext == "rs" || (markdown && ext == "md"));
+
+    (a && b && c).then_some(Ok(entry_path))
+}
+
+/// The output of applying the actions to a file.
+struct FileOutput {
+    /// The path of the file.
+    path: PathBuf,
+    /// The functions with problems found by `check`.
+    findings: Vec<Finding>,
+    /// The warning messages.
+    warnings: Vec<String>,
+    /// The messages for functions intentionally not instrumented.
+    skipped: Vec<String>,
+    /// The identifiers of the parameter types.
+    arg_types: Vec<String>,
+    /// The number of instrument attributes removed.
+    removed: usize,
+    /// The number of functions checked.
+    functions: usize,
+    /// The number of functions checked which are instrumented.
+    covered: usize,
+    /// The number of functions instrumented.
+    instrumented: usize,
+}
+
+/// Totals across the processed files.
+#[derive(Default)]
+struct Totals {
+    /// The number of files processed.
+    files: usize,
+    /// The number of functions checked.
+    functions: usize,
+    /// The number of functions missing instrumentation.
+    missing: usize,
+    /// The number of instrument attributes removed.
+    removed: usize,
+    /// The number of files with instrument attributes removed.
+    removed_files: usize,
+    /// The number of warnings emitted.
+    warnings: usize,
+    /// The number of functions instrumented.
+    instrumented: usize,
+    /// The number of parameters of each type.
+    arg_types: BTreeMap<String, usize>,
+    /// The paths of the files with functions checked, with the number of instrumented functions
+    /// and functions missing instrumentation.
+    by_file: Vec<(PathBuf, usize, usize)>,
+}
+impl Totals {
+    /// Adds the output of a file to the totals.
+    fn add(&mut self, output: &FileOutput) {
+        self.files += 1;
+        self.functions += output.functions;
+        self.warnings += output.warnings.len();
+        self.instrumented += output.instrumented;
+        for arg_type in &output.arg_types {
+            *self.arg_types.entry(arg_type.clone()).or_default() += 1;
         }
-        Action::Check => {
-            let mut visitor = CheckVisitor(None);
-            visitor.visit_file(&ast);
-            Ok(visitor.0)
+        let missing = output
+            .findings
+            .iter()
+            .filter(|finding| matches!(finding.problem, Problem::Missing))
+            .count();
+        self.missing += missing;
+        if output.functions > 0 {
+            self.by_file
+                .push((output.path.clone(), output.covered, missing));
         }
-        Action::Fix => {
-            let mut visitor = FixVisitor {
-                suffix,
-                cfg_attr,
-                list: SegmentedList {
-                    first: String::new(),
-                    inner: text
-                        .split('\n')
-                        .map(|x| (String::from(x), String::new()))
-                        .collect(),
-                },
-            };
-            visitor.visit_file(&ast);
-            let out = String::from(visitor);
-            target(source)
-                .map_err(ApplyError::Target)?
-                .write_all(out.as_bytes())
-                .map_err(ApplyError::Write)?;
-            Ok(None)
+        if output.removed > 0 {
+            self.removed += output.removed;
+            self.removed_files += 1;
         }
     }
+    /// Returns the summary of `check` as a JSON object.
+    fn summary_json(&self) -> String {
+        format!(
+            "{{\"files_scanned\": {}, \"functions_total\": {}, \"missing\": {}}}\n",
+            self.files, self.functions, self.missing
+        )
+    }
 }
 
-/// Finds the `#[instrument]` attribute on a function.
-fn find_instrumented(attrs: &[syn::Attribute]) -> Option<&syn::Attribute> {
-    attrs.iter().find(|attr| {
-        match &attr.meta {
-            syn::Meta::List(syn::MetaList { path, tokens, .. }) => matches!(path.segments.last(), Some(syn::PathSegment { ident, .. }) if ident == "instrument") ||
-                (matches!(path.segments.last(), Some(syn::PathSegment { ident, .. }) if ident == "cfg_attr") && tokens.clone().into_iter().any(|token| matches!(token, proc_macro2::TokenTree::Ident(ident) if ident == "instrument"))),
-            syn::Meta::Path(syn::Path { segments, .. }) => matches!(segments.last(), Some(syn::PathSegment { ident, .. }) if ident == "instrument"),
-            syn::Meta::NameValue(_) => false,
+/// Returns the options for the file at the given path, with its lines changed since the
+/// `--only-changed-functions` ref if set.
+fn file_options<'a>(
+    options: &'a Options,
+    changed: Option<&ChangedLines>,
+    entry_path: &Path,
+) -> Cow<'a, Options> {
+    let Some(files) = changed else {
+        return Cow::Borrowed(options);
+    };
+    // Files without changes have no changed lines.
+    let lines = entry_path
+        .canonicalize()
+        .ok()
+        .and_then(|canonical| files.get(&canonical))
+        .cloned()
+        .unwrap_or_default();
+    let mut file_options = options.clone();
+    file_options.changed_lines = Some(lines);
+    Cow::Owned(file_options)
+}
+
+/// Returns the path the result of the actions on the file at the given path is written to, the
+/// file itself or its mirror under `--output-dir`, or `None` with `--dry-run`.
+fn write_path<'a>(args: &CommandLineArgs, entry_path: &'a Path) -> Option<Cow<'a, Path>> {
+    if args.dry_run {
+        return None;
+    }
+    let Some(output_dir) = &args.output_dir else {
+        return Some(Cow::Borrowed(entry_path));
+    };
+    let root = args.path.as_deref().unwrap_or(Path::new("."));
+    // A `--path` to a single file is mirrored as that file within the output directory.
+    let relative = if root == entry_path {
+        entry_path.file_name().map_or(entry_path, Path::new)
+    } else {
+        entry_path.strip_prefix(root).unwrap_or(entry_path)
+    };
+    Some(Cow::Owned(output_dir.join(relative)))
+}
+
+/// Applies the actions to the given source of the file at the given path, writing the result to
+/// `write_path` if given.
+fn apply_file<R: Read>(
+    actions: &[Action],
+    options: &Options,
+    write_path: Option<&Path>,
+    changed: Option<&ChangedLines>,
+    entry_path: &Path,
+    source: R,
+) -> Result<FileOutput, ExecError> {
+    let file_options = file_options(options, changed, entry_path);
+    let written = Cell::new(false);
+    let mirror = |path: &Path| -> Result<(), std::io::Error> {
+        // The mirrored directories under `--output-dir` may not exist yet.
+        if path != entry_path {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
         }
+        Ok(())
+    };
+    let target = |_| -> Result<Box<dyn Write>, std::io::Error> {
+        written.set(true);
+        let Some(path) = write_path else {
+            return Ok(Box::new(std::io::sink()));
+        };
+        mirror(path)?;
+        let file = OpenOptions::new()
+            .write(true)
+            .create(path != entry_path)
+            .truncate(true)
+            .open(path)?;
+        Ok(Box::new(file))
+    };
+    // Only `.md` files under `--markdown` are walked.
+    let res = if entry_path.extension().is_some_and(|ext| ext == "md") {
+        apply_markdown(actions, &file_options, source, target)
+    } else {
+        apply_actions(actions, &file_options, source, target)
+    }
+    .map_err(|err| ExecError::Apply(entry_path.to_path_buf(), err))?;
+    // Unchanged files are not written, so are copied as they are under `--output-dir`.
+    if let Some(path) = write_path.filter(|path| {
+        !written.get() && *path != entry_path && actions.iter().any(|a| *a != Action::Check)
+    }) {
+        mirror(path)
+            .and_then(|()| std::fs::copy(entry_path, path))
+            .map_err(|err| ExecError::File(path.to_path_buf(), err))?;
+    }
+
+    // Only walked with `--include-build-rs`.
+    let build_rs = entry_path.ends_with("build.rs").then(|| {
+        format!(
+            "Warning: Processing build script {}, build scripts usually run before tracing is set up.",
+            entry_path.display()
+        )
+    });
+    let warnings = build_rs
+        .into_iter()
+        .chain(res.warnings.into_iter().map(|(span, message)| {
+            format!(
+                "Warning: {message} at {}:{}:{}.",
+                entry_path.display(),
+                span.start().line,
+                span.start().column
+            )
+        }))
+        .collect();
+    let skipped = res
+        .skipped
+        .into_iter()
+        .map(|(span, reason)| {
+            format!(
+                "{}:{}: skipped ({reason})",
+                entry_path.display(),
+                span.start().line
+            )
+        })
+        .collect();
+    let findings = findings(
+        entry_path,
+        res.problems,
+        &res.explanations,
+        &res.scopes,
+        options.behavior.named_messages,
+    );
+    Ok(FileOutput {
+        path: entry_path.to_path_buf(),
+        findings,
+        warnings,
+        skipped,
+        arg_types: res.arg_types,
+        removed: res.removed,
+        functions: res.functions,
+        covered: res.covered,
+        instrumented: res.instrumented,
+    })
+}
+
+/// Applies the actions to the source from stdin, writing the result to stdout.
+fn apply_stdin(args: &CommandLineArgs, options: &Options) -> Result<Vec<Finding>, ExecError> {
+    let path = PathBuf::from("<stdin>");
+    let mut source = Vec::new();
+    std::io::stdin()
+        .lock()
+        .read_to_end(&mut source)
+        .map_err(|err| ExecError::File(path.clone(), err))?;
+    let written = Cell::new(false);
+    let res = apply_actions(&args.action, options, source.as_slice(), |_| {
+        written.set(true);
+        Ok(std::io::stdout().lock())
     })
+    .map_err(|err| ExecError::Apply(path.clone(), err))?;
+    // Unchanged sources are not written, so are echoed as they are.
+    if !written.get() && args.action.iter().any(|action| *action != Action::Check) {
+        std::io::stdout()
+            .lock()
+            .write_all(&source)
+            .map_err(|err| ExecError::File(path.clone(), err))?;
+    }
+    Ok(findings(
+        &path,
+        res.problems,
+        &res.explanations,
+        &res.scopes,
+        options.behavior.named_messages,
+    ))
 }
 
-/// The description of attributes on a function signature we care about.
-struct Desc {
-    /// Does the function have the `#[tracing::instrument]` attribute macro?
-    instrumented: bool,
-    /// Does the function have the `#[clippy_tracing_attributes::clippy_tracing_skip]` attribute macro?
-    skipped: bool,
-    /// Does the function have the `#[test]` attribute macro?
-    test: bool,
-}
-
-// A function is considered instruments if it has the `#[instrument]` attribute or the `#[test]`
-// attribute.
-/// Returns a tuple where the 1st element is whether `tracing::instrument` is found in the list of
-/// attributes and the 2nd is whether `clippy_tracing_attributes::skip` is found in the list of
-/// attributes.
-fn check_attributes(attrs: &[syn::Attribute]) -> Desc {
-    let mut instrumented = false;
-    let mut skipped = false;
-    let mut test = false;
-
-    for attr in attrs {
-        // Match `#[cfg_attr(.., instrument)]`.
-        if match &attr.meta {
-            syn::Meta::List(syn::MetaList { path, tokens, .. }) => {
-                matches!(path.segments.last(), Some(syn::PathSegment { ident, .. }) if ident == "cfg_attr") && tokens.clone().into_iter().any(|token| matches!(token, proc_macro2::TokenTree::Ident(ident) if ident == "instrument"))
+/// Returns the findings for the given problems in the file at the given path, with the given
+/// explanations of the functions missing instrumentation and enclosing scopes of the functions,
+/// with messages naming the functions with `named`.
+fn findings(
+    path: &Path,
+    problems: Vec<(proc_macro2::Span, String, Problem)>,
+    explanations: &[(proc_macro2::Span, String)],
+    scopes: &[(proc_macro2::Span, String)],
+    named: bool,
+) -> Vec<Finding> {
+    problems
+        .into_iter()
+        .map(|(span, ident, problem)| {
+            let explanation = explanations
+                .iter()
+                .find(|(explained, _)| {
+                    matches!(problem, Problem::Missing) && explained.start() == span.start()
+                })
+                .map(|(_, explanation)| explanation.clone());
+            let scope = scopes
+                .iter()
+                .find(|(scoped, _)| scoped.start() == span.start())
+                .map(|(_, scope)| scope.clone())
+                .unwrap_or_default();
+            Finding {
+                path: path.to_path_buf(),
+                line: span.start().line,
+                column: span.start().column,
+                ident,
+                scope,
+                problem,
+                explanation,
+                named,
             }
-            _ => false
-        } {
-            instrumented = true;
-        }
+        })
+        .collect()
+}
+
+/// The state of files processed in order, shared with the files after them.
+#[derive(Default)]
+struct InOrder {
+    /// The number of functions instrumented with `--limit`.
+    instrumented: Cell<usize>,
+    /// The answer for all later functions with `--interactive`, `true` for `all` and `false`
+    /// for `quit`.
+    answer: Arc<Mutex<Option<bool>>>,
+}
+impl InOrder {
+    /// Returns whether no more files are processed as the `--limit` is reached or `quit` was
+    /// answered.
+    fn done(&self, args: &CommandLineArgs) -> bool {
+        args.limit
+            .is_some_and(|limit| self.instrumented.get() >= limit)
+            || self
+                .answer
+                .lock()
+                .is_ok_and(|answer| *answer == Some(false))
+    }
+}
+
+/// Applies the actions to the file at the given path, limited by the number of functions already
+/// instrumented with `--limit` and prompting with `--interactive`, see [`apply_file`].
+fn apply_in_order(
+    args: &CommandLineArgs,
+    options: &Options,
+    changed: Option<&ChangedLines>,
+    in_order: &InOrder,
+    entry_path: &Path,
+) -> Result<FileOutput, ExecError> {
+    let source = OpenOptions::new()
+        .read(true)
+        .open(entry_path)
+        .map_err(|err| ExecError::File(entry_path.to_path_buf(), err))?;
+    let ordered = (args.limit.is_some() || args.interactive).then(|| {
+        let path = entry_path.to_path_buf();
+        let answer = Arc::clone(&in_order.answer);
+        let mut ordered = options.clone();
+        ordered.limit = args
+            .limit
+            .map(|limit| limit.saturating_sub(in_order.instrumented.get()));
+        ordered.confirm = args.interactive.then(|| -> Confirm {
+            Arc::new(move |span, ident, attr| confirm(&path, span, ident, attr, &answer))
+        });
+        ordered
+    });
+    let output = apply_file(
+        &args.action,
+        ordered.as_ref().unwrap_or(options),
+        write_path(args, entry_path).as_deref(),
+        changed,
+        entry_path,
+        source,
+    )?;
+    in_order
+        .instrumented
+        .set(in_order.instrumented.get() + output.instrumented);
+    Ok(output)
+}
 
-        // Match `#[instrument]`.
-        if match &attr.meta {
-            syn::Meta::List(syn::MetaList { path, .. }) => {
-                matches!(path.segments.last(), Some(syn::PathSegment { ident, .. }) if ident == "instrument")
+/// Prompts on stdin whether to insert the given instrument attribute for the function with the
+/// given span and identifier in the file at the given path, unless `all` or `quit` was answered.
+fn confirm(
+    path: &Path,
+    span: proc_macro2::Span,
+    ident: &str,
+    attr: &str,
+    answer: &Mutex<Option<bool>>,
+) -> bool {
+    let Ok(mut all) = answer.lock() else {
+        return false;
+    };
+    if let Some(insert) = *all {
+        return insert;
+    }
+    loop {
+        print!(
+            "{}:{}:{}: {attr}\nInstrument `{ident}`? [y/n/all/quit] ",
+            path.display(),
+            span.start().line,
+            span.start().column
+        );
+        // Failing to flush only delays the prompt.
+        let _flush = std::io::stdout().flush();
+        let mut line = String::new();
+        // The end of stdin is treated as `quit`.
+        if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            *all = Some(false);
+            return false;
+        }
+        match line.trim() {
+            "y" | "yes" => return true,
+            "n" | "no" => return false,
+            "all" => {
+                *all = Some(true);
+                return true;
             }
-            syn::Meta::Path(syn::Path { segments, .. }) => {
-                matches!(segments.last(), Some(syn::PathSegment { ident, .. }) if ident == "instrument")
+            "quit" => {
+                *all = Some(false);
+                return false;
             }
-            syn::Meta::NameValue(_) => false,
-        } {
-            instrumented = true;
+            _ => {}
         }
+    }
+}
 
-        // Match `#[test]` or `#[kani::proof]`.
-        if match &attr.meta {
-            syn::Meta::List(syn::MetaList { path, .. }) => {
-                matches!(path.segments.last(), Some(syn::PathSegment { ident, .. }) if ident == "proof")
-            }
-            syn::Meta::Path(syn::Path { segments, .. }) => {
-                matches!(segments.last(), Some(syn::PathSegment { ident, .. }) if ident == "test" || ident == "proof")
+/// Processes files in a pipeline where `read_threads` threads read files and pass them to
+/// `parse_threads` threads, returning the results in the order of `files`.
+fn pipeline<'scope, F, R, P, T>(
+    scope: &'scope std::thread::Scope<'scope, '_>,
+    files: F,
+    read_threads: NonZeroUsize,
+    parse_threads: NonZeroUsize,
+    read: &'scope R,
+    parse: &'scope P,
+) -> Ordered<Result<T, ExecError>>
+where
+    F: Iterator<Item = Result<PathBuf, ExecError>> + Send + 'scope,
+    R: Fn(&Path) -> Result<Vec<u8>, ExecError> + Sync,
+    P: Fn(&Path, Vec<u8>) -> Result<T, ExecError> + Sync,
+    T: Send + 'scope,
+{
+    /// Receives from a receiver shared between threads.
+    fn recv<X>(receiver: &Mutex<mpsc::Receiver<X>>) -> Option<X> {
+        receiver.lock().ok()?.recv().ok()
+    }
+
+    let (paths_sender, paths_receiver) = mpsc::channel();
+    let (sources_sender, sources_receiver) = mpsc::sync_channel(parse_threads.get());
+    let (results_sender, results_receiver) = mpsc::channel();
+    let paths = Arc::new(Mutex::new(paths_receiver));
+    let sources = Arc::new(Mutex::new(sources_receiver));
+
+    scope.spawn(move || {
+        for (i, file) in files.enumerate() {
+            if paths_sender.send((i, file)).is_err() {
+                break;
             }
-            syn::Meta::NameValue(_) => false,
-        } {
-            test = true;
         }
-
-        // Match `#[clippy_tracing_skip]`.
-        if match &attr.meta {
-            syn::Meta::List(syn::MetaList { path, .. }) => {
-                matches!(path.segments.last(), Some(syn::PathSegment { ident, .. }) if ident == "clippy_tracing_skip")
+    });
+    for _ in 0..read_threads.get() {
+        let receiver = Arc::clone(&paths);
+        let sender = sources_sender.clone();
+        scope.spawn(move || {
+            while let Some((i, file)) = recv(&receiver) {
+                let source = file.and_then(|path| read(&path).map(|buf| (path, buf)));
+                if sender.send((i, source)).is_err() {
+                    break;
+                }
             }
-            syn::Meta::Path(syn::Path { segments, .. }) => {
-                matches!(segments.last(), Some(syn::PathSegment { ident, .. }) if ident == "clippy_tracing_skip")
+        });
+    }
+    for _ in 0..parse_threads.get() {
+        let receiver = Arc::clone(&sources);
+        let sender = results_sender.clone();
+        scope.spawn(move || {
+            while let Some((i, source)) = recv(&receiver) {
+                let result = source.and_then(|(path, buf)| parse(&path, buf));
+                if sender.send((i, result)).is_err() {
+                    break;
+                }
             }
-            syn::Meta::NameValue(_) => false,
-        } {
-            skipped = true;
-        }
+        });
     }
-    Desc {
-        instrumented,
-        skipped,
-        test,
+    Ordered {
+        receiver: results_receiver,
+        buffer: BTreeMap::new(),
+        next: 0,
+    }
+}
+
+/// Iterator returning the indexed items received from a channel in order of their indices.
+struct Ordered<T> {
+    /// The receiver of indexed items.
+    receiver: mpsc::Receiver<(usize, T)>,
+    /// Items received before their turn.
+    buffer: BTreeMap<usize, T>,
+    /// The index of the next item.
+    next: usize,
+}
+impl<T> Iterator for Ordered<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        loop {
+            if let Some(item) = self.buffer.remove(&self.next) {
+                self.next += 1;
+                return Some(item);
+            }
+            let (i, item) = self.receiver.recv().ok()?;
+            self.buffer.insert(i, item);
+        }
     }
 }
@@ -1,22 +1,40 @@
 use std::fs::{remove_file, OpenOptions};
 use std::io::{Read, Write};
+use std::path::Path;
 use std::process::Command;
 
 const BINARY: &str = env!("CARGO_BIN_EXE_clippy-tracing");
 
+fn temp_path(name: &str) -> String {
+    std::env::temp_dir().join(name).display().to_string()
+}
+
 fn setup(text: &str) -> String {
     let id = uuid::Uuid::new_v4();
-    let path = format!("/tmp/{id}.rs");
+    let path = temp_path(&format!("{id}.rs"));
     let mut file = OpenOptions::new()
         .create(true)
         .read(false)
         .write(true)
+        .truncate(true)
         .open(&path)
         .unwrap();
     file.write_all(text.as_bytes()).unwrap();
     path
 }
 
+fn setup_dir(files: &[(String, String)]) -> String {
+    let id = uuid::Uuid::new_v4();
+    let dir = temp_path(&id.to_string());
+    std::fs::create_dir(&dir).unwrap();
+    for (name, text) in files {
+        let file = Path::new(&dir).join(name);
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(file, text).unwrap();
+    }
+    dir
+}
+
 fn check_file(text: &str, path: &str) {
     let mut file = OpenOptions::new()
         .create(false)
@@ -30,170 +48,3069 @@ fn check_file(text: &str, path: &str) {
 }
 
 fn fix(given: &str, expected: &str, cfg_attr: Option<&'static str>) {
+    if let Some(cfg_attr) = cfg_attr {
+        fix_args(given, expected, &["--cfg-attr", cfg_attr]);
+    } else {
+        fix_args(given, expected, &[]);
+    }
+}
+
+fn fix_args(given: &str, expected: &str, args: &[&str]) {
     let path = setup(given);
 
-    let output = if let Some(cfg_attr) = cfg_attr {
-        Command::new(BINARY)
-            .args(["--action", "fix", "--path", &path, "--cfg-attr", cfg_attr])
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &path])
+        .args(args)
+        .output()
+        .unwrap();
+    assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "");
+    assert_eq!(std::str::from_utf8(&output.stderr).unwrap(), "");
+    assert_eq!(output.status.code(), Some(0));
+    check_file(expected, &path);
+    remove_file(path).unwrap();
+}
+
+fn strip(given: &str, expected: &str) {
+    strip_args(given, expected, &[]);
+}
+
+fn strip_args(given: &str, expected: &str, args: &[&str]) {
+    let path = setup(given);
+    let output = Command::new(BINARY)
+        .args(["--action", "strip", "--path", &path])
+        .args(args)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    assert_eq!(output.stderr, []);
+    check_file(expected, &path);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn exec_error() {
+    // Create file path for a file that doesn't exist.
+    let id = uuid::Uuid::new_v4();
+    let path = temp_path(&format!("{id}.rs"));
+
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, []);
+    // The OS error message differs between platforms.
+    let expected_stderr =
+        format!("Error: Failed to read entry in file path: IO error for operation on {path}: ");
+    assert!(std::str::from_utf8(&output.stderr)
+        .unwrap()
+        .starts_with(&expected_stderr));
+}
+
+#[test]
+fn max_depth() {
+    let nested = |depth: usize| {
+        let open = (0..depth)
+            .map(|i| format!("mod m{i} {{\n"))
+            .collect::<String>();
+        format!("{open}fn main() {{ }}\n{}", "}\n".repeat(depth))
+    };
+
+    // Within the maximum depth.
+    let path = setup(&nested(32));
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path, "--max-depth", "64"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let expected_stdout = format!("Missing instrumentation for `main` at {path}:33:0.\n");
+    assert_eq!(output.stdout, expected_stdout.as_bytes());
+    assert_eq!(output.stderr, []);
+    remove_file(path).unwrap();
+
+    // Deep expressions are not limited by default.
+    #[cfg(not(feature = "log"))]
+    const INSTRUMENT: &str = "#[tracing::instrument(level = \"trace\", skip())]\n";
+    #[cfg(feature = "log")]
+    const INSTRUMENT: &str = "#[log_instrument::instrument]\n";
+    let deep = format!(
+        "fn f() -> i32 {{\n    {}1{}\n}}",
+        "(".repeat(70),
+        ")".repeat(70)
+    );
+    fix(&deep, &format!("{INSTRUMENT}{deep}"), None);
+
+    // Exceeding the maximum depth.
+    let path = setup(&nested(10_000));
+    for threads in ["1", "2"] {
+        let output = Command::new(BINARY)
+            .args(["--action", "check", "--path", &path, "--max-depth", "64"])
+            .args(["--parse-threads", threads])
             .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(output.stdout, []);
+        assert!(std::str::from_utf8(&output.stderr)
             .unwrap()
-    } else {
-        Command::new(BINARY)
-            .args(["--action", "fix", "--path", &path])
+            .contains("File nesting exceeds the maximum depth of 64."));
+    }
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn allow_fragments() {
+    const GIVEN: &str = "let x = 1;\nfn helper(y: u8) -> u8 {\n    y\n}\nhelper(x);";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "let x = 1;\n#[tracing::instrument(level = \"trace\", skip(y))]\nfn helper(y: u8) -> u8 {\n    y\n}\nhelper(x);";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str =
+        "let x = 1;\n#[log_instrument::instrument]\nfn helper(y: u8) -> u8 {\n    y\n}\nhelper(x);";
+
+    // Without `--allow-fragments` the file fails to parse.
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    check_file(GIVEN, &path);
+    remove_file(path).unwrap();
+
+    fix_args(GIVEN, EXPECTED, &["--allow-fragments"]);
+    strip_args(EXPECTED, GIVEN, &["--allow-fragments"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn read_only_error() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = setup("fn main() {}");
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o444)).unwrap();
+    // Permissions are not enforced for privileged users.
+    if OpenOptions::new().write(true).open(&path).is_ok() {
+        remove_file(path).unwrap();
+        return;
+    }
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, []);
+    let expected_stderr = format!("Error: Failed to run apply function on {path}: Failed to get write target: Permission denied (os error 13)\n");
+    assert_eq!(
+        std::str::from_utf8(&output.stderr).unwrap(),
+        expected_stderr
+    );
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn fix_one() {
+    const GIVEN: &str = "fn main() { }\nfn add(lhs: i32, rhs: i32) {\n    lhs + rhs\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn main() { }\n#[tracing::instrument(level = \"trace\", skip(lhs, rhs))]\nfn add(lhs: i32, rhs: i32) {\n    lhs + rhs\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\nfn main() { }\n#[log_instrument::instrument]\nfn add(lhs: i32, rhs: i32) {\n    lhs + rhs\n}";
+    fix(GIVEN, EXPECTED, None);
+}
+
+#[test]
+fn fix_trailing_newline() {
+    #[cfg(not(feature = "log"))]
+    const ATTR: &str = "#[tracing::instrument(level = \"trace\", skip())]";
+    #[cfg(feature = "log")]
+    const ATTR: &str = "#[log_instrument::instrument]";
+    // The function as the only, first, middle and last item.
+    let cases = [
+        (
+            String::from("fn one() {}"),
+            format!("{ATTR}\nfn one() {{}}"),
+        ),
+        (
+            String::from("fn one() {}\nstruct Two;"),
+            format!("{ATTR}\nfn one() {{}}\nstruct Two;"),
+        ),
+        (
+            String::from("struct Zero;\nfn one() {}\nstruct Two;"),
+            format!("struct Zero;\n{ATTR}\nfn one() {{}}\nstruct Two;"),
+        ),
+        (
+            String::from("struct Zero;\nfn one() {\n    two();\n}"),
+            format!("struct Zero;\n{ATTR}\nfn one() {{\n    two();\n}}"),
+        ),
+    ];
+    for (given, expected) in cases {
+        // No trailing newline is added.
+        fix(&given, &expected, None);
+        strip(&expected, &given);
+        // A trailing newline is kept.
+        fix(&format!("{given}\n"), &format!("{expected}\n"), None);
+        strip(&format!("{expected}\n"), &format!("{given}\n"));
+    }
+}
+
+#[test]
+fn fix_two() {
+    const GIVEN: &str = "impl Unit {\n    fn one() {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str =
+        "impl Unit {\n    #[tracing::instrument(level = \"trace\", skip())]\n    fn one() {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "impl Unit {\n    #[log_instrument::instrument]\n    fn one() {}\n}";
+    fix(GIVEN, EXPECTED, None);
+}
+
+#[test]
+fn fix_three() {
+    const GIVEN: &str = "impl Unit {\n    fn one() {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str =
+        "impl Unit {\n    #[cfg_attr(feature = \"tracing\", tracing::instrument(level = \"trace\", skip()))]\n    fn one() {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "impl Unit {\n    #[cfg_attr(feature = \"tracing\", log_instrument::instrument)]\n    fn one() {}\n}";
+    fix(GIVEN, EXPECTED, Some("feature = \"tracing\""));
+}
+
+#[test]
+fn fix_enum_impl() {
+    const GIVEN: &str = "enum Unit {\n    One,\n}\nimpl Unit {\n    fn one(&self) {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "enum Unit {\n    One,\n}\nimpl Unit {\n    #[tracing::instrument(level = \"trace\", skip(self))]\n    fn one(&self) {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "enum Unit {\n    One,\n}\nimpl Unit {\n    #[log_instrument::instrument]\n    fn one(&self) {}\n}";
+    fix(GIVEN, EXPECTED, None);
+}
+
+#[test]
+fn fix_tuple_struct_impl() {
+    const GIVEN: &str = "struct Pair(u8, u8);\nimpl Pair {\n    fn sum(&self) -> u8 {\n        self.0 + self.1\n    }\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "struct Pair(u8, u8);\nimpl Pair {\n    #[tracing::instrument(level = \"trace\", skip(self))]\n    fn sum(&self) -> u8 {\n        self.0 + self.1\n    }\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "struct Pair(u8, u8);\nimpl Pair {\n    #[log_instrument::instrument]\n    fn sum(&self) -> u8 {\n        self.0 + self.1\n    }\n}";
+    fix(GIVEN, EXPECTED, None);
+}
+
+#[test]
+fn fix_closure_static() {
+    const GIVEN: &str = "static HANDLER: fn() = || {\n    println!(\"handled\");\n};\nconst DOUBLE: fn(u8) -> u8 = |x| x * 2;\nfn one() {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "static HANDLER: fn() = || {\n    println!(\"handled\");\n};\nconst DOUBLE: fn(u8) -> u8 = |x| x * 2;\n#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "static HANDLER: fn() = || {\n    println!(\"handled\");\n};\nconst DOUBLE: fn(u8) -> u8 = |x| x * 2;\n#[log_instrument::instrument]\nfn one() {}";
+    fix(GIVEN, EXPECTED, None);
+    strip(EXPECTED, GIVEN);
+
+    // Only the free function is reported.
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let expected_stdout = format!("Missing instrumentation for `one` at {path}:5:0.\n");
+    assert_eq!(output.stdout, expected_stdout.as_bytes());
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn fix_trait() {
+    const GIVEN: &str = "trait Unit {\n    fn one(&self);\n    fn two(&self) {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "trait Unit {\n    fn one(&self);\n    #[tracing::instrument(level = \"trace\", skip(self))]\n    fn two(&self) {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str =
+        "trait Unit {\n    fn one(&self);\n    #[log_instrument::instrument]\n    fn two(&self) {}\n}";
+    fix_args(GIVEN, EXPECTED, &["--trait-methods"]);
+    strip(EXPECTED, GIVEN);
+
+    // Without `--trait-methods`
+    fix(GIVEN, GIVEN, None);
+}
+
+#[test]
+fn fix_trait_async() {
+    const GIVEN: &str = "trait Unit {\n    async fn one(&self) -> u32 {\n        1\n    }\n    fn two(&self) -> impl Future<Output = u32> {\n        async { 2 }\n    }\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "trait Unit {\n    #[tracing::instrument(level = \"trace\", skip(self), ret)]\n    async fn one(&self) -> u32 {\n        1\n    }\n    #[tracing::instrument(level = \"trace\", skip(self))]\n    fn two(&self) -> impl Future<Output = u32> {\n        async { 2 }\n    }\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "trait Unit {\n    #[log_instrument::instrument]\n    async fn one(&self) -> u32 {\n        1\n    }\n    #[log_instrument::instrument]\n    fn two(&self) -> impl Future<Output = u32> {\n        async { 2 }\n    }\n}";
+    fix_args(GIVEN, EXPECTED, &["--ret", "--trait-methods"]);
+    strip(EXPECTED, GIVEN);
+}
+
+#[test]
+fn fix_compat() {
+    const GIVEN: &str = "fn add(lhs: i32, rhs: i32) -> i32 {\n    lhs + rhs\n}\ntrait Unit {\n    fn one(&self) {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip(lhs, rhs))]\nfn add(lhs: i32, rhs: i32) -> i32 {\n    lhs + rhs\n}\ntrait Unit {\n    fn one(&self) {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\nfn add(lhs: i32, rhs: i32) -> i32 {\n    lhs + rhs\n}\ntrait Unit {\n    fn one(&self) {}\n}";
+    let files = [
+        (String::from("src/add.rs"), String::from(GIVEN)),
+        (String::from("target/add.rs"), String::from(GIVEN)),
+    ];
+    let fix_dir = |args: &[&str]| {
+        let dir = setup_dir(&files);
+        let output = Command::new(BINARY)
+            .args(["--action", "fix", "--path", &dir])
+            .args(args)
             .output()
-            .unwrap()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(output.stderr, []);
+        let read = |name: &str| std::fs::read_to_string(Path::new(&dir).join(name)).unwrap();
+        let texts = (read("src/add.rs"), read("target/add.rs"));
+        std::fs::remove_dir_all(dir).unwrap();
+        texts
     };
-    assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "");
-    assert_eq!(std::str::from_utf8(&output.stderr).unwrap(), "");
+
+    // `target` directories are skipped since 0.8.0, while the `skip`-only attribute is that of
+    // 0.1.
+    assert_eq!(fix_dir(&[]), (String::from(EXPECTED), String::from(GIVEN)));
+    assert_eq!(
+        fix_dir(&["--compat", "0.1"]),
+        (String::from(EXPECTED), String::from(EXPECTED))
+    );
+
+    // This release has its own defaults.
+    let version = env!("CARGO_PKG_VERSION");
+    assert_eq!(
+        fix_dir(&["--compat", version]),
+        (String::from(EXPECTED), String::from(GIVEN))
+    );
+}
+
+#[test]
+fn fix_include_const() {
+    const GIVEN: &str = "const fn one() {}\nimpl Unit {\n    const fn two() {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\nconst fn one() {}\nimpl Unit {\n    #[tracing::instrument(level = \"trace\", skip())]\n    const fn two() {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\nconst fn one() {}\nimpl Unit {\n    #[log_instrument::instrument]\n    const fn two() {}\n}";
+
+    // Without `--include-const`
+    fix(GIVEN, GIVEN, None);
+
+    // With `--include-const`
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &path, "--include-const"])
+        .output()
+        .unwrap();
     assert_eq!(output.status.code(), Some(0));
-    check_file(expected, &path);
+    assert_eq!(output.stdout, []);
+    let expected_stderr = format!("Warning: Instrumenting `const fn`, this may fail to compile at {path}:1:0.\nWarning: Instrumenting `const fn`, this may fail to compile at {path}:3:4.\n");
+    assert_eq!(
+        std::str::from_utf8(&output.stderr).unwrap(),
+        expected_stderr
+    );
+    check_file(EXPECTED, &path);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn fix_wrapped_signature() {
+    // The indentation is that of the first line of the function, not of the body brace.
+    const GIVEN: &str = "impl Unit {\n    pub fn long(\n        a: i32,\n    ) -> i32\n                {\n        a\n    }\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "impl Unit {\n    #[tracing::instrument(level = \"trace\", skip(a))]\n    pub fn long(\n        a: i32,\n    ) -> i32\n                {\n        a\n    }\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "impl Unit {\n    #[log_instrument::instrument]\n    pub fn long(\n        a: i32,\n    ) -> i32\n                {\n        a\n    }\n}";
+    fix(GIVEN, EXPECTED, None);
+    strip(EXPECTED, GIVEN);
+}
+
+#[test]
+fn fix_method_modifiers() {
+    // The attribute is placed above all the modifiers of the signature.
+    const GIVEN: &str = "impl Unit {\n    async fn one(&self) {}\n    unsafe fn two(&self) {}\n    const unsafe fn three() {}\n    pub async unsafe fn four(&self) {}\n    pub(crate) unsafe extern \"C\" fn five() {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "impl Unit {\n    #[tracing::instrument(level = \"trace\", skip(self))]\n    async fn one(&self) {}\n    #[tracing::instrument(level = \"trace\", skip(self))]\n    unsafe fn two(&self) {}\n    const unsafe fn three() {}\n    #[tracing::instrument(level = \"trace\", skip(self))]\n    pub async unsafe fn four(&self) {}\n    #[tracing::instrument(level = \"trace\", skip())]\n    pub(crate) unsafe extern \"C\" fn five() {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "impl Unit {\n    #[log_instrument::instrument]\n    async fn one(&self) {}\n    #[log_instrument::instrument]\n    unsafe fn two(&self) {}\n    const unsafe fn three() {}\n    #[log_instrument::instrument]\n    pub async unsafe fn four(&self) {}\n    #[log_instrument::instrument]\n    pub(crate) unsafe extern \"C\" fn five() {}\n}";
+    fix(GIVEN, EXPECTED, None);
+    strip(EXPECTED, GIVEN);
+
+    // With `--include-const`
+    #[cfg(not(feature = "log"))]
+    const INCLUDE_CONST: &str = "impl Unit {\n    #[tracing::instrument(level = \"trace\", skip(self))]\n    async fn one(&self) {}\n    #[tracing::instrument(level = \"trace\", skip(self))]\n    unsafe fn two(&self) {}\n    #[tracing::instrument(level = \"trace\", skip())]\n    const unsafe fn three() {}\n    #[tracing::instrument(level = \"trace\", skip(self))]\n    pub async unsafe fn four(&self) {}\n    #[tracing::instrument(level = \"trace\", skip())]\n    pub(crate) unsafe extern \"C\" fn five() {}\n}";
+    #[cfg(feature = "log")]
+    const INCLUDE_CONST: &str = "impl Unit {\n    #[log_instrument::instrument]\n    async fn one(&self) {}\n    #[log_instrument::instrument]\n    unsafe fn two(&self) {}\n    #[log_instrument::instrument]\n    const unsafe fn three() {}\n    #[log_instrument::instrument]\n    pub async unsafe fn four(&self) {}\n    #[log_instrument::instrument]\n    pub(crate) unsafe extern \"C\" fn five() {}\n}";
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &path, "--include-const"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    assert_eq!(
+        std::str::from_utf8(&output.stderr).unwrap(),
+        format!("Warning: Instrumenting `const fn`, this may fail to compile at {path}:4:4.\n")
+    );
+    check_file(INCLUDE_CONST, &path);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn fix_warn_macro_rules() {
+    const GIVEN: &str =
+        "macro_rules! adder {\n    ($name:ident) => {\n        fn $name() {}\n    };\n}";
+
+    // Without `--warn-macro-rules`
+    fix(GIVEN, GIVEN, None);
+
+    // With `--warn-macro-rules`
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &path, "--warn-macro-rules"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    let expected_stderr = format!("Warning: Function defined in `macro_rules!` body will not be instrumented at {path}:3:8.\n");
+    assert_eq!(
+        std::str::from_utf8(&output.stderr).unwrap(),
+        expected_stderr
+    );
+    check_file(GIVEN, &path);
+
+    // With `--fail-on-warnings`
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &path, "--warn-macro-rules"])
+        .args(["--fail-on-warnings", "--quiet"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, []);
+    assert_eq!(
+        std::str::from_utf8(&output.stderr).unwrap(),
+        "Error: Emitted 1 warnings.\n"
+    );
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn fix_track_caller() {
+    const GIVEN: &str = "#[track_caller]\nfn one() {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str =
+        "#[tracing::instrument(level = \"trace\", skip())]\n#[track_caller]\nfn one() {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\n#[track_caller]\nfn one() {}";
+
+    // Without `--warn-track-caller`
+    fix(GIVEN, EXPECTED, None);
+
+    // With `--warn-track-caller`
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &path, "--warn-track-caller"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    let expected_stderr = format!("Warning: Instrumenting `#[track_caller]` function, this may change `Location::caller()` at {path}:1:0.\n");
+    assert_eq!(
+        std::str::from_utf8(&output.stderr).unwrap(),
+        expected_stderr
+    );
+    check_file(EXPECTED, &path);
     remove_file(path).unwrap();
 }
 
-fn strip(given: &str, expected: &str) {
-    let path = setup(given);
+#[test]
+fn fix_main_level() {
+    const GIVEN: &str = "fn main() {}\nfn add() {}\nmod inner {\n    fn main() {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"info\", skip())]\nfn main() {}\n#[tracing::instrument(level = \"trace\", skip())]\nfn add() {}\nmod inner {\n    #[tracing::instrument(level = \"trace\", skip())]\n    fn main() {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\nfn main() {}\n#[log_instrument::instrument]\nfn add() {}\nmod inner {\n    #[log_instrument::instrument]\n    fn main() {}\n}";
+    fix_args(GIVEN, EXPECTED, &["--main-level", "info"]);
+}
+
+#[test]
+fn fix_record_type() {
+    const GIVEN: &str = "fn one(id: uuid::Uuid, bytes: Vec<u8>, addr: SocketAddr) {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip(bytes))]\nfn one(id: uuid::Uuid, bytes: Vec<u8>, addr: SocketAddr) {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\nfn one(id: uuid::Uuid, bytes: Vec<u8>, addr: SocketAddr) {}";
+    fix_args(GIVEN, EXPECTED, &["--record-type", "Uuid,SocketAddr"]);
+}
+
+#[test]
+fn fix_record_receiver() {
+    const GIVEN: &str = "impl Unit {\n    fn one(&self, bytes: Vec<u8>) {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "impl Unit {\n    #[tracing::instrument(level = \"trace\", skip(self, bytes))]\n    fn one(&self, bytes: Vec<u8>) {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str =
+        "impl Unit {\n    #[log_instrument::instrument]\n    fn one(&self, bytes: Vec<u8>) {}\n}";
+    fix(GIVEN, EXPECTED, None);
+
+    // With `--record-receiver`
+    #[cfg(not(feature = "log"))]
+    const RECORD_RECEIVER: &str = "impl Unit {\n    #[tracing::instrument(level = \"trace\", skip(bytes))]\n    fn one(&self, bytes: Vec<u8>) {}\n}";
+    #[cfg(feature = "log")]
+    const RECORD_RECEIVER: &str = EXPECTED;
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &path, "--record-receiver"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    #[cfg(not(feature = "log"))]
+    let expected_stderr =
+        format!("Warning: Recording `self` of `one`, this requires `Self: Debug` at {path}:2:4.\n");
+    #[cfg(feature = "log")]
+    let expected_stderr = String::new();
+    assert_eq!(
+        std::str::from_utf8(&output.stderr).unwrap(),
+        expected_stderr
+    );
+    check_file(RECORD_RECEIVER, &path);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn fix_skip_boilerplate() {
+    const GIVEN: &str = "impl fmt::Display for Unit {\n    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {\n        Ok(())\n    }\n}\nimpl Clone for Unit {\n    fn clone(&self) -> Self {\n        Self\n    }\n}\nimpl Unit {\n    fn process(&self) {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "impl fmt::Display for Unit {\n    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {\n        Ok(())\n    }\n}\nimpl Clone for Unit {\n    fn clone(&self) -> Self {\n        Self\n    }\n}\nimpl Unit {\n    #[tracing::instrument(level = \"trace\", skip(self))]\n    fn process(&self) {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "impl fmt::Display for Unit {\n    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {\n        Ok(())\n    }\n}\nimpl Clone for Unit {\n    fn clone(&self) -> Self {\n        Self\n    }\n}\nimpl Unit {\n    #[log_instrument::instrument]\n    fn process(&self) {}\n}";
+    fix_args(GIVEN, EXPECTED, &["--skip-boilerplate"]);
+
+    // With `--skip-method`
+    #[cfg(not(feature = "log"))]
+    const SKIP_PROCESS: &str = "impl Unit {\n    fn process(&self) {}\n    #[tracing::instrument(level = \"trace\", skip(self))]\n    fn clone(&self) {}\n}";
+    #[cfg(feature = "log")]
+    const SKIP_PROCESS: &str = "impl Unit {\n    fn process(&self) {}\n    #[log_instrument::instrument]\n    fn clone(&self) {}\n}";
+    fix_args(
+        "impl Unit {\n    fn process(&self) {}\n    fn clone(&self) {}\n}",
+        SKIP_PROCESS,
+        &["--skip-method", "process"],
+    );
+}
+
+#[test]
+fn fix_same_line() {
+    const GIVEN: &str =
+        "fn one() {} fn two() {}\nimpl Unit { fn three() {} }\n    mod inner { fn four() {} }";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}\n#[tracing::instrument(level = \"trace\", skip())]\nfn two() {}\nimpl Unit {\n#[tracing::instrument(level = \"trace\", skip())]\nfn three() {} }\n    mod inner {\n    #[tracing::instrument(level = \"trace\", skip())]\n    fn four() {} }";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\nfn one() {}\n#[log_instrument::instrument]\nfn two() {}\nimpl Unit {\n#[log_instrument::instrument]\nfn three() {} }\n    mod inner {\n    #[log_instrument::instrument]\n    fn four() {} }";
+    fix(GIVEN, EXPECTED, None);
+
+    // The attribute is on its own line on the first line and when nested.
+    const NESTED: &str = "fn one() {\n    fn two() {}\n}";
+    #[cfg(not(feature = "log"))]
+    const NESTED_EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn one() {\n    #[tracing::instrument(level = \"trace\", skip())]\n    fn two() {}\n}";
+    #[cfg(feature = "log")]
+    const NESTED_EXPECTED: &str = "#[log_instrument::instrument]\nfn one() {\n    #[log_instrument::instrument]\n    fn two() {}\n}";
+    fix(NESTED, NESTED_EXPECTED, None);
+
+    // Every function starting on a line is split onto its own line so none are lost.
+    const DENSE: &str = "fn a(){} fn b(){} fn c(){}\nimpl Unit { fn d(&self){} fn e(&self){} }";
+    #[cfg(not(feature = "log"))]
+    const DENSE_EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn a(){}\n#[tracing::instrument(level = \"trace\", skip())]\nfn b(){}\n#[tracing::instrument(level = \"trace\", skip())]\nfn c(){}\nimpl Unit {\n#[tracing::instrument(level = \"trace\", skip(self))]\nfn d(&self){}\n#[tracing::instrument(level = \"trace\", skip(self))]\nfn e(&self){} }";
+    #[cfg(feature = "log")]
+    const DENSE_EXPECTED: &str = "#[log_instrument::instrument]\nfn a(){}\n#[log_instrument::instrument]\nfn b(){}\n#[log_instrument::instrument]\nfn c(){}\nimpl Unit {\n#[log_instrument::instrument]\nfn d(&self){}\n#[log_instrument::instrument]\nfn e(&self){} }";
+    fix(DENSE, DENSE_EXPECTED, None);
+    // The split functions are then instrumented.
+    fix(DENSE_EXPECTED, DENSE_EXPECTED, None);
+}
+
+#[test]
+fn fix_skip_deprecated() {
+    const GIVEN: &str = "#[deprecated]\nfn one() {}\n#[deprecated(note = \"use `three`\")]\nfn two() {}\nfn three() {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[deprecated]\nfn one() {}\n#[deprecated(note = \"use `three`\")]\nfn two() {}\n#[tracing::instrument(level = \"trace\", skip())]\nfn three() {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[deprecated]\nfn one() {}\n#[deprecated(note = \"use `three`\")]\nfn two() {}\n#[log_instrument::instrument]\nfn three() {}";
+    fix_args(GIVEN, EXPECTED, &["--skip-deprecated"]);
+}
+
+#[test]
+fn fix_ret() {
+    const GIVEN: &str = "fn one() {}\nfn two() -> () {}\nfn three() -> u32 {\n    3\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}\n#[tracing::instrument(level = \"trace\", skip())]\nfn two() -> () {}\n#[tracing::instrument(level = \"trace\", skip(), ret)]\nfn three() -> u32 {\n    3\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\nfn one() {}\n#[log_instrument::instrument]\nfn two() -> () {}\n#[log_instrument::instrument]\nfn three() -> u32 {\n    3\n}";
+    fix_args(GIVEN, EXPECTED, &["--ret"]);
+    strip(EXPECTED, GIVEN);
+}
+
+#[test]
+fn fix_otel_name() {
+    const GIVEN: &str =
+        "fn one() {}\nmod inner {\n    impl Unit {\n        fn two(&self) {}\n    }\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip(), fields(otel.name = \"one\"))]\nfn one() {}\nmod inner {\n    impl Unit {\n        #[tracing::instrument(level = \"trace\", skip(self), fields(otel.name = \"inner::Unit::two\"))]\n        fn two(&self) {}\n    }\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\nfn one() {}\nmod inner {\n    impl Unit {\n        #[log_instrument::instrument]\n        fn two(&self) {}\n    }\n}";
+    fix_args(GIVEN, EXPECTED, &["--otel-name"]);
+    strip(EXPECTED, GIVEN);
+
+    // Names match the baseline keys of `check`.
+    const TRAIT: &str = "impl Default for Unit {\n    fn default() -> Self {\n        fn new() {}\n        Self\n    }\n}";
+    #[cfg(not(feature = "log"))]
+    const TRAIT_EXPECTED: &str = "impl Default for Unit {\n    #[tracing::instrument(level = \"trace\", skip(), fields(otel.name = \"<Unit as Default>::default\"))]\n    fn default() -> Self {\n        #[tracing::instrument(level = \"trace\", skip(), fields(otel.name = \"<Unit as Default>::default::new\"))]\n        fn new() {}\n        Self\n    }\n}";
+    #[cfg(feature = "log")]
+    const TRAIT_EXPECTED: &str = "impl Default for Unit {\n    #[log_instrument::instrument]\n    fn default() -> Self {\n        #[log_instrument::instrument]\n        fn new() {}\n        Self\n    }\n}";
+    fix_args(TRAIT, TRAIT_EXPECTED, &["--otel-name"]);
+}
+
+#[test]
+fn fix_gap_lines() {
+    const GIVEN: &str =
+        "// A comment.\nfn one() {}\nstruct Two;\n\nfn two() {}\nimpl Unit {\n    fn three() {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "// A comment.\n\n#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}\nstruct Two;\n\n#[tracing::instrument(level = \"trace\", skip())]\nfn two() {}\nimpl Unit {\n    #[tracing::instrument(level = \"trace\", skip())]\n    fn three() {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "// A comment.\n\n#[log_instrument::instrument]\nfn one() {}\nstruct Two;\n\n#[log_instrument::instrument]\nfn two() {}\nimpl Unit {\n    #[log_instrument::instrument]\n    fn three() {}\n}";
+    // Existing blank lines count towards the gap and none are added at the start of a block.
+    fix_args(GIVEN, EXPECTED, &["--gap-lines", "1"]);
+
+    // With `--place-below` the gap is below the preceding attribute.
+    const DOC: &str = "/// Documented.\n#[inline]\nfn one() {}";
+    #[cfg(not(feature = "log"))]
+    const DOC_EXPECTED: &str = "/// Documented.\n#[inline]\n\n#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}";
+    #[cfg(feature = "log")]
+    const DOC_EXPECTED: &str =
+        "/// Documented.\n#[inline]\n\n#[log_instrument::instrument]\nfn one() {}";
+    fix_args(
+        DOC,
+        DOC_EXPECTED,
+        &["--gap-lines", "1", "--place-below", "inline"],
+    );
+
+    // Functions split from the preceding code on their line are also separated by the gap.
+    const DENSE: &str = "fn a(){} fn b(){}\nimpl Unit { fn c(&self){} fn d(&self){} }";
+    #[cfg(not(feature = "log"))]
+    const DENSE_EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn a(){}\n\n#[tracing::instrument(level = \"trace\", skip())]\nfn b(){}\nimpl Unit {\n#[tracing::instrument(level = \"trace\", skip(self))]\nfn c(&self){}\n\n#[tracing::instrument(level = \"trace\", skip(self))]\nfn d(&self){} }";
+    #[cfg(feature = "log")]
+    const DENSE_EXPECTED: &str = "#[log_instrument::instrument]\nfn a(){}\n\n#[log_instrument::instrument]\nfn b(){}\nimpl Unit {\n#[log_instrument::instrument]\nfn c(&self){}\n\n#[log_instrument::instrument]\nfn d(&self){} }";
+    fix_args(DENSE, DENSE_EXPECTED, &["--gap-lines", "1"]);
+}
+
+#[test]
+fn fix_root_fn() {
+    const GIVEN: &str = "fn worker() {}\nfn helper() {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip(), parent = None)]\nfn worker() {}\n#[tracing::instrument(level = \"trace\", skip())]\nfn helper() {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\nfn worker() {}\n#[log_instrument::instrument]\nfn helper() {}";
+    fix_args(GIVEN, EXPECTED, &["--root-fn", "worker"]);
+}
+
+#[test]
+fn fix_limit() {
+    const GIVEN: &str = "fn one() {}\nfn two() {}\nfn three() {}\nfn four() {}\nfn five() {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}\n#[tracing::instrument(level = \"trace\", skip())]\nfn two() {}\nfn three() {}\nfn four() {}\nfn five() {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\nfn one() {}\n#[log_instrument::instrument]\nfn two() {}\nfn three() {}\nfn four() {}\nfn five() {}";
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &path, "--limit", "2"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        "Instrumented 2 functions.\n"
+    );
+    assert_eq!(output.stderr, []);
+    check_file(EXPECTED, &path);
+    remove_file(path).unwrap();
+
+    // The limit is shared across files, the file after the limit is reached is left unchanged.
+    let dir = setup_dir(&[
+        (
+            String::from("a.rs"),
+            String::from("fn one() {}\nfn two() {}"),
+        ),
+        (String::from("b.rs"), String::from("fn three() {}")),
+    ]);
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &dir, "--limit", "1"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        "Instrumented 1 functions.\n"
+    );
+    let a = std::fs::read_to_string(Path::new(&dir).join("a.rs")).unwrap();
+    let b = std::fs::read_to_string(Path::new(&dir).join("b.rs")).unwrap();
+    assert_eq!(a.matches("#[").count() + b.matches("#[").count(), 1);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn fix_multibyte() {
+    // Columns are in characters, so multibyte characters earlier on a line do not offset them.
+    const GIVEN: &str = "// 🦀 ünïcödé\nimpl Ünit {\n    // 日本語\n    fn one() {}\n}\nconst É: u8 = 0; fn two() {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "// 🦀 ünïcödé\nimpl Ünit {\n    // 日本語\n    #[tracing::instrument(level = \"trace\", skip())]\n    fn one() {}\n}\nconst É: u8 = 0;\n#[tracing::instrument(level = \"trace\", skip())]\nfn two() {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "// 🦀 ünïcödé\nimpl Ünit {\n    // 日本語\n    #[log_instrument::instrument]\n    fn one() {}\n}\nconst É: u8 = 0;\n#[log_instrument::instrument]\nfn two() {}";
+    fix(GIVEN, EXPECTED, None);
+
+    let path = setup(GIVEN);
+    let baseline = format!("{path}.baseline");
+    std::fs::write(&baseline, "").unwrap();
+    let output = Command::new(BINARY)
+        .args([
+            "--action",
+            "check",
+            "--path",
+            &path,
+            "--baseline",
+            &baseline,
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        format!("Missing instrumentation for `one` at {path}:4:4.\nMissing instrumentation for `two` at {path}:6:17.\n")
+    );
+    remove_file(path).unwrap();
+    remove_file(baseline).unwrap();
+}
+
+#[test]
+fn fix_generic_params() {
+    // Lifetime, type and const generic parameters are not skipped as they are not values.
+    const GIVEN: &str = "fn one<'a>(x: &'a str) {}\nfn two<const N: usize>(arr: [u8; N]) {}\nfn three<'a, T, const N: usize>(t: &'a [T; N]) {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip(x))]\nfn one<'a>(x: &'a str) {}\n#[tracing::instrument(level = \"trace\", skip(arr))]\nfn two<const N: usize>(arr: [u8; N]) {}\n#[tracing::instrument(level = \"trace\", skip(t))]\nfn three<'a, T, const N: usize>(t: &'a [T; N]) {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\nfn one<'a>(x: &'a str) {}\n#[log_instrument::instrument]\nfn two<const N: usize>(arr: [u8; N]) {}\n#[log_instrument::instrument]\nfn three<'a, T, const N: usize>(t: &'a [T; N]) {}";
+    fix(GIVEN, EXPECTED, None);
+    strip(EXPECTED, GIVEN);
+}
+
+#[test]
+fn fix_interactive() {
+    const GIVEN: &str = "fn one() {}\nfn two() {}\nfn three() {}";
+    #[cfg(not(feature = "log"))]
+    const ATTR: &str = "#[tracing::instrument(level = \"trace\", skip())]";
+    #[cfg(feature = "log")]
+    const ATTR: &str = "#[log_instrument::instrument]";
+    let run = |answers: &str| {
+        let path = setup(GIVEN);
+        let mut child = Command::new(BINARY)
+            .args(["--action", "fix", "--path", &path, "--interactive"])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(answers.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(output.stderr, []);
+        let fixed = std::fs::read_to_string(&path).unwrap();
+        remove_file(&path).unwrap();
+        (path, String::from_utf8(output.stdout).unwrap(), fixed)
+    };
+
+    // Unrecognized answers are asked again, the end of stdin quits.
+    let (path, stdout, fixed) = run("y\nmaybe\nn\n");
+    let prompt = |line: usize, ident: &str| {
+        format!("{path}:{line}:0: {ATTR}\nInstrument `{ident}`? [y/n/all/quit] ")
+    };
+    assert_eq!(
+        stdout,
+        [
+            prompt(1, "one"),
+            prompt(2, "two"),
+            prompt(2, "two"),
+            prompt(3, "three")
+        ]
+        .concat()
+    );
+    assert_eq!(
+        fixed,
+        format!("{ATTR}\nfn one() {{}}\nfn two() {{}}\nfn three() {{}}")
+    );
+
+    // `all` accepts the remaining functions without asking.
+    let (path, stdout, fixed) = run("n\nall\n");
+    let prompt = |line: usize, ident: &str| {
+        format!("{path}:{line}:0: {ATTR}\nInstrument `{ident}`? [y/n/all/quit] ")
+    };
+    assert_eq!(stdout, [prompt(1, "one"), prompt(2, "two")].concat());
+    assert_eq!(
+        fixed,
+        format!("fn one() {{}}\n{ATTR}\nfn two() {{}}\n{ATTR}\nfn three() {{}}")
+    );
+}
+
+#[test]
+fn fix_tag_comment() {
+    const GIVEN: &str = "fn one() {}\nimpl Unit {\n    fn two() {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())] // clippy-tracing generated\nfn one() {}\nimpl Unit {\n    #[tracing::instrument(level = \"trace\", skip())] // clippy-tracing generated\n    fn two() {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument] // clippy-tracing generated\nfn one() {}\nimpl Unit {\n    #[log_instrument::instrument] // clippy-tracing generated\n    fn two() {}\n}";
+    fix_args(GIVEN, EXPECTED, &["--tag-comment"]);
+    strip(EXPECTED, GIVEN);
+}
+
+#[test]
+fn multiple_actions() {
+    const GIVEN: &str = "fn one() {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\nfn one() {}";
+    // Checks the fixed source.
+    fix_args(GIVEN, EXPECTED, &["--action", "check"]);
+
+    // Checks the source before fixing it.
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--action", "fix", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let expected_stdout = format!("Missing instrumentation for `one` at {path}:1:0.\n");
+    assert_eq!(output.stdout, expected_stdout.as_bytes());
+    check_file(EXPECTED, &path);
+    remove_file(path).unwrap();
+
+    // Files after the first finding are still fixed.
+    let files = [
+        (String::from("a.rs"), String::from(GIVEN)),
+        (String::from("b.rs"), String::from(GIVEN)),
+    ];
+    let dir = setup_dir(&files);
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--action", "fix", "--path", &dir])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap().lines().count(),
+        1
+    );
+    for (name, _) in &files {
+        check_file(EXPECTED, &Path::new(&dir).join(name).display().to_string());
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn treats_as_instrumented() {
+    const GIVEN: &str = "#[traced]\nfn one() {}\nimpl Unit {\n    #[my::traced(level = \"debug\")]\n    fn two() {}\n}";
+    fix_args(GIVEN, GIVEN, &["--treats-as-instrumented", "traced"]);
+
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args([
+            "--action",
+            "check",
+            "--path",
+            &path,
+            "--treats-as-instrumented",
+            "traced",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn check_strict_instrument_path() {
+    #[cfg(not(feature = "log"))]
+    const GIVEN: &str = "#[other::instrument]\nfn one() {}\n#[::tracing::instrument]\nfn two() {}";
+    #[cfg(feature = "log")]
+    const GIVEN: &str =
+        "#[other::instrument]\nfn one() {}\n#[::log_instrument::instrument]\nfn two() {}";
+    #[cfg(not(feature = "log"))]
+    const DUPLICATED: &str = "#[other::instrument]\n#[tracing::instrument]\nfn one() {}";
+    #[cfg(feature = "log")]
+    const DUPLICATED: &str = "#[other::instrument]\n#[log_instrument::instrument]\nfn one() {}";
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+
+    let output = Command::new(BINARY)
+        .args([
+            "--action",
+            "check",
+            "--path",
+            &path,
+            "--strict-instrument-path",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        format!("Missing instrumentation for `one` at {path}:1:0.\n")
+    );
+    remove_file(path).unwrap();
+
+    // Only the attributes with the full instrument path are stripped or deduplicated.
+    strip(GIVEN, "fn one() {}\nfn two() {}");
+    strip_args(
+        GIVEN,
+        "#[other::instrument]\nfn one() {}\nfn two() {}",
+        &["--strict-instrument-path"],
+    );
+    let path = setup(DUPLICATED);
+    let output = Command::new(BINARY)
+        .args([
+            "--action",
+            "dedupe",
+            "--path",
+            &path,
+            "--strict-instrument-path",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    check_file(DUPLICATED, &path);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn fix_skip_trivial() {
+    const GIVEN: &str = "impl Unit {\n    fn x(&self) -> u32 {\n        self.x\n    }\n    fn y(&self) -> u32 {\n        (self.x + 1) * 2\n    }\n    fn compute(&self) -> u32 {\n        expensive()\n    }\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "impl Unit {\n    fn x(&self) -> u32 {\n        self.x\n    }\n    fn y(&self) -> u32 {\n        (self.x + 1) * 2\n    }\n    #[tracing::instrument(level = \"trace\", skip(self))]\n    fn compute(&self) -> u32 {\n        expensive()\n    }\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "impl Unit {\n    fn x(&self) -> u32 {\n        self.x\n    }\n    fn y(&self) -> u32 {\n        (self.x + 1) * 2\n    }\n    #[log_instrument::instrument]\n    fn compute(&self) -> u32 {\n        expensive()\n    }\n}";
+    fix_args(GIVEN, EXPECTED, &["--skip-trivial"]);
+}
+
+#[test]
+fn missing_exit_code() {
+    let path = setup("fn one() {}");
+    let expected_stdout = format!("Missing instrumentation for `one` at {path}:1:0.\n");
+    for (code, expected) in [("3", 3), ("1000", 255)] {
+        let output = Command::new(BINARY)
+            .args([
+                "--action",
+                "check",
+                "--path",
+                &path,
+                "--missing-exit-code",
+                code,
+            ])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(expected));
+        assert_eq!(output.stdout, expected_stdout.as_bytes());
+    }
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn check_max_output() {
+    let path = setup("fn one() {}\nfn two() {}\nfn three() {}\nfn four() {}\nfn five() {}");
+    let expected_stdout = format!("Missing instrumentation for `one` at {path}:1:0.\nMissing instrumentation for `two` at {path}:2:0.\n... and 3 more\n");
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path, "--max-output", "2"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        expected_stdout
+    );
+
+    // With a baseline
+    let baseline = format!("{path}.baseline");
+    std::fs::write(&baseline, "").unwrap();
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path, "--max-output", "2"])
+        .args(["--baseline", &baseline])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        expected_stdout
+    );
+    remove_file(path).unwrap();
+    remove_file(baseline).unwrap();
+}
+
+#[test]
+fn fix_newline_style() {
+    const GIVEN: &str = "fn one() {}\r\nfn two() {}\r\n";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\r\nfn one() {}\r\n#[tracing::instrument(level = \"trace\", skip())]\r\nfn two() {}\r\n";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\r\nfn one() {}\r\n#[log_instrument::instrument]\r\nfn two() {}\r\n";
+    // `\r\n` line endings are kept by default.
+    fix(GIVEN, EXPECTED, None);
+    fix_args(
+        &GIVEN.replace('\r', ""),
+        EXPECTED,
+        &["--newline-style", "crlf"],
+    );
+    fix_args(
+        GIVEN,
+        &EXPECTED.replace('\r', ""),
+        &["--newline-style", "lf"],
+    );
+    strip_args(
+        EXPECTED,
+        &GIVEN.replace('\r', ""),
+        &["--newline-style", "lf"],
+    );
+}
+
+#[test]
+fn fix_mixed_newlines() {
+    const GIVEN: &str = "fn one() {}\r\n\nfn two() {}\n// end\r\n";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\r\nfn one() {}\r\n\n#[tracing::instrument(level = \"trace\", skip())]\nfn two() {}\n// end\r\n";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\r\nfn one() {}\r\n\n#[log_instrument::instrument]\nfn two() {}\n// end\r\n";
+    // Each line keeps its line ending, inserted lines use that of the line after them.
+    fix(GIVEN, EXPECTED, None);
+    strip(EXPECTED, GIVEN);
+    // Including when the inserted and original lines repeat.
+    const REPEATED: &str = "mod a {\r\n    fn one() {}\r\n}\nmod b {\n    fn one() {}\n}\n";
+    #[cfg(not(feature = "log"))]
+    const REPEATED_EXPECTED: &str = "mod a {\r\n    #[tracing::instrument(level = \"trace\", skip())]\r\n    fn one() {}\r\n}\nmod b {\n    #[tracing::instrument(level = \"trace\", skip())]\n    fn one() {}\n}\n";
+    #[cfg(feature = "log")]
+    const REPEATED_EXPECTED: &str = "mod a {\r\n    #[log_instrument::instrument]\r\n    fn one() {}\r\n}\nmod b {\n    #[log_instrument::instrument]\n    fn one() {}\n}\n";
+    fix(REPEATED, REPEATED_EXPECTED, None);
+    strip(REPEATED_EXPECTED, REPEATED);
+
+    // Sources which are unchanged are not written.
+    let path = setup(EXPECTED);
+    let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    check_file(EXPECTED, &path);
+    assert_eq!(
+        std::fs::metadata(&path).unwrap().modified().unwrap(),
+        modified
+    );
+    remove_file(path).unwrap();
+
+    // Unchanged stdin is echoed as it is.
+    let mut child = Command::new(BINARY)
+        .args(["--action", "fix", "--diff-against-stdin"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(EXPECTED.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), EXPECTED);
+}
+
+#[test]
+fn fix_same_name() {
+    const GIVEN: &str = "fn run() {}\nimpl One {\n    fn run(&self) {}\n}\nimpl Two for Unit {\n    fn run(&self, x: u8) {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn run() {}\nimpl One {\n    #[tracing::instrument(level = \"trace\", skip(self))]\n    fn run(&self) {}\n}\nimpl Two for Unit {\n    #[tracing::instrument(level = \"trace\", skip(self, x))]\n    fn run(&self, x: u8) {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\nfn run() {}\nimpl One {\n    #[log_instrument::instrument]\n    fn run(&self) {}\n}\nimpl Two for Unit {\n    #[log_instrument::instrument]\n    fn run(&self, x: u8) {}\n}";
+    fix(GIVEN, EXPECTED, None);
+    strip(EXPECTED, GIVEN);
+
+    // Each function is reported at its own span, all are reported with an empty baseline.
+    let path = setup(GIVEN);
+    let baseline = format!("{path}.baseline");
+    std::fs::write(&baseline, "").unwrap();
+    let output = Command::new(BINARY)
+        .args([
+            "--action",
+            "check",
+            "--path",
+            &path,
+            "--baseline",
+            &baseline,
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        format!("Missing instrumentation for `run` at {path}:1:0.\nMissing instrumentation for `run` at {path}:3:4.\nMissing instrumentation for `run` at {path}:6:4.\n")
+    );
+    remove_file(path).unwrap();
+    remove_file(baseline).unwrap();
+}
+
+#[test]
+fn fix_only_fn() {
+    const GIVEN: &str = "fn main() {}\nfn run() {}\nfn helper() {}\nimpl Unit {\n    fn run(&self) {}\n    fn stop(&self) {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn main() {}\n#[tracing::instrument(level = \"trace\", skip())]\nfn run() {}\nfn helper() {}\nimpl Unit {\n    #[tracing::instrument(level = \"trace\", skip(self))]\n    fn run(&self) {}\n    fn stop(&self) {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\nfn main() {}\n#[log_instrument::instrument]\nfn run() {}\nfn helper() {}\nimpl Unit {\n    #[log_instrument::instrument]\n    fn run(&self) {}\n    fn stop(&self) {}\n}";
+    fix_args(GIVEN, EXPECTED, &["--only-fn", "main", "--only-fn", "run"]);
+}
+
+#[test]
+fn fix_only_free_fns_methods() {
+    const GIVEN: &str = "fn one() {}\nimpl Unit {\n    fn two(&self) {}\n}\ntrait Three {\n    fn three(&self) {}\n}";
+    #[cfg(not(feature = "log"))]
+    const FREE_FNS: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}\nimpl Unit {\n    fn two(&self) {}\n}\ntrait Three {\n    fn three(&self) {}\n}";
+    #[cfg(feature = "log")]
+    const FREE_FNS: &str = "#[log_instrument::instrument]\nfn one() {}\nimpl Unit {\n    fn two(&self) {}\n}\ntrait Three {\n    fn three(&self) {}\n}";
+    #[cfg(not(feature = "log"))]
+    const METHODS: &str = "fn one() {}\nimpl Unit {\n    #[tracing::instrument(level = \"trace\", skip(self))]\n    fn two(&self) {}\n}\ntrait Three {\n    #[tracing::instrument(level = \"trace\", skip(self))]\n    fn three(&self) {}\n}";
+    #[cfg(feature = "log")]
+    const METHODS: &str = "fn one() {}\nimpl Unit {\n    #[log_instrument::instrument]\n    fn two(&self) {}\n}\ntrait Three {\n    #[log_instrument::instrument]\n    fn three(&self) {}\n}";
+    fix_args(GIVEN, FREE_FNS, &["--only-free-fns"]);
+    fix_args(GIVEN, METHODS, &["--only-methods", "--trait-methods"]);
+
+    // The flags are mutually exclusive.
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &path])
+        .args(["--only-free-fns", "--only-methods"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(std::str::from_utf8(&output.stderr)
+        .unwrap()
+        .contains("cannot be used with"));
+    check_file(GIVEN, &path);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn fix_refresh() {
+    const GIVEN: &str = "#[inline]\n#[tracing::instrument]\nfn one(x: u8) {}\nimpl Unit {\n    #[tracing::instrument(\n        level = \"debug\",\n        skip(self)\n    )]\n    #[inline]\n    fn two(&self, y: u8) {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[inline]\n#[tracing::instrument(skip(x))]\nfn one(x: u8) {}\nimpl Unit {\n    #[tracing::instrument(level = \"debug\", skip(self, y))]\n    #[inline]\n    fn two(&self, y: u8) {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[inline]\n#[tracing::instrument]\nfn one(x: u8) {}\nimpl Unit {\n    #[tracing::instrument(level = \"debug\", skip(self))]\n    #[inline]\n    fn two(&self, y: u8) {}\n}";
+    fix_args(GIVEN, EXPECTED, &["--refresh"]);
+    // Without `--refresh` existing instrumentation is unchanged.
+    fix(GIVEN, GIVEN, None);
+
+    // Only `skip(..)` is regenerated, the other arguments are kept as written.
+    const ARGS: &str = "#[tracing::instrument(name = \"custom\", skip_all, err)]\nfn one(a: u8) -> Result<(), ()> {\n    Ok(())\n}\n#[tracing::instrument(name = \"two\", skip(a), fields(otel.name = \"x\"), ret, parent = None)]\nfn two(a: u8, b: u8) -> u8 {\n    a\n}";
+    #[cfg(not(feature = "log"))]
+    const ARGS_EXPECTED: &str = "#[tracing::instrument(name = \"custom\", skip_all, err)]\nfn one(a: u8) -> Result<(), ()> {\n    Ok(())\n}\n#[tracing::instrument(name = \"two\", skip(a, b), fields(otel.name = \"x\"), ret, parent = None)]\nfn two(a: u8, b: u8) -> u8 {\n    a\n}";
+    #[cfg(feature = "log")]
+    const ARGS_EXPECTED: &str = ARGS;
+    fix_args(ARGS, ARGS_EXPECTED, &["--refresh"]);
+
+    // With `--strict-instrument-path` only the attribute with the full path is regenerated.
+    #[cfg(not(feature = "log"))]
+    const STRICT: &str =
+        "#[other::instrument(skip())]\n#[tracing::instrument(skip())]\nfn one(a: u8) {}";
+    #[cfg(feature = "log")]
+    const STRICT: &str =
+        "#[other::instrument(skip())]\n#[log_instrument::instrument]\nfn one(a: u8) {}";
+    #[cfg(not(feature = "log"))]
+    const STRICT_EXPECTED: &str =
+        "#[other::instrument(skip())]\n#[tracing::instrument(skip(a))]\nfn one(a: u8) {}";
+    #[cfg(feature = "log")]
+    const STRICT_EXPECTED: &str = STRICT;
+    fix_args(
+        STRICT,
+        STRICT_EXPECTED,
+        &["--refresh", "--strict-instrument-path"],
+    );
+}
+
+#[test]
+fn fix_trailing_comma() {
+    const GIVEN: &str = "fn one() {}\nfn two(a: u8, b: u8) {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}\n#[tracing::instrument(level = \"trace\", skip(a, b,))]\nfn two(a: u8, b: u8) {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\nfn one() {}\n#[log_instrument::instrument]\nfn two(a: u8, b: u8) {}";
+    fix_args(GIVEN, EXPECTED, &["--trailing-comma"]);
+    strip(EXPECTED, GIVEN);
+
+    // The instrumentation still parses and is recognized.
+    let path = setup(EXPECTED);
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    assert_eq!(output.stderr, []);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn fix_with_err() {
+    const GIVEN: &str = "fn one() -> Result<(), Box<dyn Error>> {}\nfn two() -> std::result::Result<u8, String> {}\nfn three() -> anyhow::Result<()> {}\nfn four() -> Fallible<()> {}\nfn five() -> Option<u8> {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip(), err)]\nfn one() -> Result<(), Box<dyn Error>> {}\n#[tracing::instrument(level = \"trace\", skip(), err)]\nfn two() -> std::result::Result<u8, String> {}\n#[tracing::instrument(level = \"trace\", skip(), err)]\nfn three() -> anyhow::Result<()> {}\n#[tracing::instrument(level = \"trace\", skip(), err)]\nfn four() -> Fallible<()> {}\n#[tracing::instrument(level = \"trace\", skip())]\nfn five() -> Option<u8> {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\nfn one() -> Result<(), Box<dyn Error>> {}\n#[log_instrument::instrument]\nfn two() -> std::result::Result<u8, String> {}\n#[log_instrument::instrument]\nfn three() -> anyhow::Result<()> {}\n#[log_instrument::instrument]\nfn four() -> Fallible<()> {}\n#[log_instrument::instrument]\nfn five() -> Option<u8> {}";
+    fix_args(
+        GIVEN,
+        EXPECTED,
+        &["--with-err", "--result-alias", "Fallible"],
+    );
+}
+
+#[test]
+fn fix_skip_extern_abi() {
+    const GIVEN: &str = "#[no_mangle]\npub extern \"C\" fn callback() {}\nfn one() {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[no_mangle]\npub extern \"C\" fn callback() {}\n#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[no_mangle]\npub extern \"C\" fn callback() {}\n#[log_instrument::instrument]\nfn one() {}";
+    fix_args(GIVEN, EXPECTED, &["--skip-extern-abi"]);
+
+    #[cfg(not(feature = "log"))]
+    const ALL: &str = "#[tracing::instrument(level = \"trace\", skip())]\n#[no_mangle]\npub extern \"C\" fn callback() {}\n#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}";
+    #[cfg(feature = "log")]
+    const ALL: &str = "#[log_instrument::instrument]\n#[no_mangle]\npub extern \"C\" fn callback() {}\n#[log_instrument::instrument]\nfn one() {}";
+    fix(GIVEN, ALL, None);
+}
+
+#[test]
+fn fix_env() {
+    const GIVEN: &str = "fn one() {}";
+    let files = [
+        (String::from("src/one.rs"), String::from(GIVEN)),
+        (String::from("gen/two.rs"), String::from(GIVEN)),
+    ];
+    let dir = setup_dir(&files);
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &dir])
+        .env("CLIPPY_TRACING_LEVEL", "debug")
+        .env("CLIPPY_TRACING_SUFFIX", "my::")
+        .env("CLIPPY_TRACING_EXCLUDE", "gen,vendor")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[my::instrument(level = \"debug\", skip())]\nfn one() {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[my::instrument]\nfn one() {}";
+    let one = Path::new(&dir).join("src").join("one.rs");
+    let two = Path::new(&dir).join("gen").join("two.rs");
+    check_file(EXPECTED, &one.display().to_string());
+    check_file(GIVEN, &two.display().to_string());
+
+    // Flags take precedence over the environment.
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &dir, "--exclude", "src"])
+        .env("CLIPPY_TRACING_EXCLUDE", "gen")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    #[cfg(not(feature = "log"))]
+    const FLAGS: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}";
+    #[cfg(feature = "log")]
+    const FLAGS: &str = "#[log_instrument::instrument]\nfn one() {}";
+    check_file(EXPECTED, &one.display().to_string());
+    check_file(FLAGS, &two.display().to_string());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn fix_entry_macros() {
+    const GIVEN: &str = "#[tokio::main]\nasync fn main() {}\n#[async_std::main]\nasync fn two() {}\n#[actix_web::main(flavor = \"current_thread\")]\nasync fn three() {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\n#[tokio::main]\nasync fn main() {}\n#[tracing::instrument(level = \"trace\", skip())]\n#[async_std::main]\nasync fn two() {}\n#[tracing::instrument(level = \"trace\", skip())]\n#[actix_web::main(flavor = \"current_thread\")]\nasync fn three() {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\n#[tokio::main]\nasync fn main() {}\n#[log_instrument::instrument]\n#[async_std::main]\nasync fn two() {}\n#[log_instrument::instrument]\n#[actix_web::main(flavor = \"current_thread\")]\nasync fn three() {}";
+    // Instrumentation is placed above the entry point macro.
+    fix(GIVEN, EXPECTED, None);
+    fix_args(GIVEN, GIVEN, &["--skip-entry-macros"]);
+}
+
+#[test]
+fn fix_allow() {
+    const GIVEN: &str = "#[allow(clippy::too_many_arguments)]\nfn one(a: u8, b: u8) {}\nimpl Unit {\n    #[expect(clippy::unused_self, reason = \"trait\")]\n    fn two(&self) {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip(a, b))]\n#[allow(clippy::too_many_arguments)]\nfn one(a: u8, b: u8) {}\nimpl Unit {\n    #[tracing::instrument(level = \"trace\", skip(self))]\n    #[expect(clippy::unused_self, reason = \"trait\")]\n    fn two(&self) {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\n#[allow(clippy::too_many_arguments)]\nfn one(a: u8, b: u8) {}\nimpl Unit {\n    #[log_instrument::instrument]\n    #[expect(clippy::unused_self, reason = \"trait\")]\n    fn two(&self) {}\n}";
+    fix(GIVEN, EXPECTED, None);
+    strip(EXPECTED, GIVEN);
+}
+
+#[test]
+fn fix_const_context() {
+    const GIVEN: &str = "const X: () = {\n    fn one() {}\n};\nimpl Unit {\n    const Y: () = {\n        fn two() {}\n    };\n}\nfn main() {\n    const {\n        fn three() {}\n    };\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "const X: () = {\n    fn one() {}\n};\nimpl Unit {\n    const Y: () = {\n        fn two() {}\n    };\n}\n#[tracing::instrument(level = \"trace\", skip())]\nfn main() {\n    const {\n        fn three() {}\n    };\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "const X: () = {\n    fn one() {}\n};\nimpl Unit {\n    const Y: () = {\n        fn two() {}\n    };\n}\n#[log_instrument::instrument]\nfn main() {\n    const {\n        fn three() {}\n    };\n}";
+    fix(GIVEN, EXPECTED, None);
+}
+
+#[test]
+fn fix_min_args() {
+    const GIVEN: &str = "fn zero() {}\nfn two(a: u8, b: u8) {}\nfn five(a: u8, b: u8, c: u8, d: u8, e: u8) {}\nimpl Unit {\n    fn method(&self, a: u8, b: u8) {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "fn zero() {}\nfn two(a: u8, b: u8) {}\n#[tracing::instrument(level = \"trace\", skip(a, b, c, d, e))]\nfn five(a: u8, b: u8, c: u8, d: u8, e: u8) {}\nimpl Unit {\n    fn method(&self, a: u8, b: u8) {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "fn zero() {}\nfn two(a: u8, b: u8) {}\n#[log_instrument::instrument]\nfn five(a: u8, b: u8, c: u8, d: u8, e: u8) {}\nimpl Unit {\n    fn method(&self, a: u8, b: u8) {}\n}";
+    fix_args(GIVEN, EXPECTED, &["--min-args", "3"]);
+
+    // Counting the receiver.
+    #[cfg(not(feature = "log"))]
+    const COUNT_SELF: &str = "fn zero() {}\nfn two(a: u8, b: u8) {}\n#[tracing::instrument(level = \"trace\", skip(a, b, c, d, e))]\nfn five(a: u8, b: u8, c: u8, d: u8, e: u8) {}\nimpl Unit {\n    #[tracing::instrument(level = \"trace\", skip(self, a, b))]\n    fn method(&self, a: u8, b: u8) {}\n}";
+    #[cfg(feature = "log")]
+    const COUNT_SELF: &str = "fn zero() {}\nfn two(a: u8, b: u8) {}\n#[log_instrument::instrument]\nfn five(a: u8, b: u8, c: u8, d: u8, e: u8) {}\nimpl Unit {\n    #[log_instrument::instrument]\n    fn method(&self, a: u8, b: u8) {}\n}";
+    fix_args(
+        GIVEN,
+        COUNT_SELF,
+        &["--min-args", "3", "--min-args-count-self"],
+    );
+
+    // Functions below the threshold are not reported by `check`.
+    let path = setup(EXPECTED);
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path, "--min-args", "3"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    assert_eq!(output.stderr, []);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn fix_min_branches() {
+    const GIVEN: &str = "fn one() {\n    let x = 1;\n}\nfn two(x: Option<u8>) -> Option<u8> {\n    for _ in 0..3 {}\n    match x? {\n        0 => None,\n        _ => Some(1),\n    }\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "fn one() {\n    let x = 1;\n}\n#[tracing::instrument(level = \"trace\", skip(x))]\nfn two(x: Option<u8>) -> Option<u8> {\n    for _ in 0..3 {}\n    match x? {\n        0 => None,\n        _ => Some(1),\n    }\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "fn one() {\n    let x = 1;\n}\n#[log_instrument::instrument]\nfn two(x: Option<u8>) -> Option<u8> {\n    for _ in 0..3 {}\n    match x? {\n        0 => None,\n        _ => Some(1),\n    }\n}";
+    fix_args(GIVEN, EXPECTED, &["--min-branches", "3"]);
+
+    // Above the number of branch points in `two`.
+    fix_args(GIVEN, GIVEN, &["--min-branches", "5"]);
+}
+
+#[test]
+fn doctor() {
+    let path = setup("fn one() {}");
+    let output = Command::new(BINARY)
+        .args(["--path", &path, "--doctor"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    assert!(stdout.starts_with(&format!("version: {}\n", env!("CARGO_PKG_VERSION"))));
+    assert!(stdout.contains(&format!("\npath: {path} exists\n")));
+    assert!(stdout.contains("\ndoctor = true\n"));
+    // No files are modified.
+    check_file("fn one() {}", &path);
+    remove_file(&path).unwrap();
+
+    let output = Command::new(BINARY)
+        .args(["--path", &path, "--doctor"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    assert!(stdout.contains(&format!("\npath: {path} does not exist\n")));
+}
+
+#[test]
+fn list_recognized() {
+    let output = Command::new(BINARY)
+        .args(["--list-recognized", "--treats-as-instrumented", "traced"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        "instrument: instrument, traced\ntest: test, proof\nskip: clippy_tracing_skip\nentry: tokio::main, async_std::main, actix_web::main\n"
+    );
+    assert_eq!(output.stderr, []);
+}
+
+#[test]
+fn diff_against_stdin() {
+    const GIVEN: &str = "fn one() {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\nfn one() {}";
+    let run = |action: &str, given: &str| {
+        let mut child = Command::new(BINARY)
+            .args(["--action", action, "--diff-against-stdin"])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(given.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    };
+
+    let output = run("fix", GIVEN);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), EXPECTED);
+    assert_eq!(output.stderr, []);
+
+    let output = run("strip", EXPECTED);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), GIVEN);
+    assert_eq!(output.stderr, []);
+
+    // Only failing to parse the source fails.
+    let output = run("fix", "fn one(");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, []);
+    assert!(std::str::from_utf8(&output.stderr)
+        .unwrap()
+        .starts_with("Error: Failed to run apply function on <stdin>: "));
+}
+
+#[test]
+fn print_config() {
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", "/tmp/nonexistent"])
+        .args(["--exclude", "target,vendor", "--suffix", "my::"])
+        .args(["--compat", "0.7", "--include-const", "--print-config"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        "action = \"fix\"
+path = \"/tmp/nonexistent\"
+suffix = \"my::\"
+exclude = [\"target\", \"vendor\"]
+compat = \"0.7.0\"
+detect-vcs-root = false
+summary-json = false
+by-file = false
+write-baseline = false
+place-below = []
+treats-as-instrumented = []
+strict-instrument-path = false
+require-skip = false
+collect-args-stats = false
+report-uninstrumentable = false
+explain = false
+level = \"trace\"
+read-threads = 1
+parse-threads = 1
+skip-cfg = []
+skip-cfg-test = false
+record-type = []
+record-receiver = false
+skip-method = []
+only-fn = []
+only-free-fns = false
+only-methods = false
+skip-boilerplate = false
+skip-deprecated = false
+skip-trivial = false
+skip-extern-abi = false
+skip-entry-macros = false
+markdown = false
+skip-out-dir = false
+include-build-rs = false
+ret = false
+otel-name = false
+root-fn = []
+with-err = false
+trailing-comma = false
+result-alias = []
+tag-comment = false
+gap-lines = 0
+refresh = false
+newline-style = \"auto\"
+warn-macro-rules = false
+warn-track-caller = false
+warn-commented-instrument = false
+fail-on-warnings = false
+error-on-empty = false
+clean-imports = false
+ensure-import = false
+compile-check = false
+progress = false
+quiet = false
+dry-run = false
+doctor = false
+list-recognized = false
+diff-against-stdin = false
+interactive = false
+allow-fragments = false
+skip-parse-errors = false
+missing-exit-code = 2
+include-const = true
+trait-methods = false
+
+[behavior]
+skip-target = false
+named-messages = false
+check-nested = false
+"
+    );
+    assert_eq!(output.stderr, []);
+}
+
+#[test]
+fn fix_compile_check() {
+    // Requires cargo.
+    if Command::new("cargo").arg("--version").output().is_err() {
+        return;
+    }
+    let manifest =
+        String::from("[package]\nname = \"scratch\"\nversion = \"0.1.0\"\nedition = \"2021\"\n");
+    let lib = String::from("pub fn one() {}");
+    let dir = setup_dir(&[
+        (String::from("Cargo.toml"), manifest),
+        (String::from("src/lib.rs"), lib),
+    ]);
+
+    // Compiles with an inactive `cfg_attr`.
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &dir, "--compile-check"])
+        .args(["--cfg-attr", "any()"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    assert_eq!(output.stderr, []);
+
+    // Fails to compile when the recorded parameter does not implement `Debug`, which the stub
+    // `tracing` requires like `tracing` does.
+    #[cfg(not(feature = "log"))]
+    const STUB: &str = "extern crate proc_macro;\nuse proc_macro::TokenStream;\n#[proc_macro_attribute]\npub fn instrument(attr: TokenStream, item: TokenStream) -> TokenStream {\n    let attr = attr.to_string();\n    let skip = attr.split(\"skip(\").nth(1).and_then(|s| s.split(')').next()).unwrap_or(\"\");\n    let skipped = skip.split(',').map(str::trim).collect::<Vec<_>>();\n    let item = item.to_string();\n    let params = &item[item.find('(').unwrap() + 1..item.find(')').unwrap()];\n    let checks = params.split(',').filter_map(|p| p.split(':').next()).map(str::trim).filter(|p| !p.is_empty() && !skipped.contains(p)).map(|p| format!(\"debug(&{p});\")).collect::<String>();\n    let body = item.find('{').unwrap();\n    format!(\"{}{{ fn debug<T: core::fmt::Debug>(_: &T) {{}} {checks} {}\", &item[..body], &item[body + 1..]).parse().unwrap()\n}";
+    // `log_instrument` does not record parameters.
+    #[cfg(feature = "log")]
+    const STUB: &str = "extern crate proc_macro;\nuse proc_macro::TokenStream;\n#[proc_macro_attribute]\npub fn instrument(_attr: TokenStream, item: TokenStream) -> TokenStream {\n    item\n}";
+    #[cfg(not(feature = "log"))]
+    const DEPENDENCY: &str = "tracing";
+    #[cfg(feature = "log")]
+    const DEPENDENCY: &str = "log_instrument";
+    let stub_manifest = format!("[package]\nname = \"{DEPENDENCY}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n[lib]\nproc-macro = true\n");
+    std::fs::create_dir_all(Path::new(&dir).join("stub/src")).unwrap();
+    std::fs::write(Path::new(&dir).join("stub/Cargo.toml"), stub_manifest).unwrap();
+    std::fs::write(Path::new(&dir).join("stub/src/lib.rs"), STUB).unwrap();
+    let manifest = format!("[package]\nname = \"scratch\"\nversion = \"0.1.0\"\nedition = \"2021\"\n[dependencies]\n{DEPENDENCY} = {{ path = \"stub\" }}\n");
+    std::fs::write(Path::new(&dir).join("Cargo.toml"), manifest).unwrap();
+    let lib = "pub struct Unit;\npub fn one(unit: Unit, x: u8) {}";
+    // The stub is outside the walked sources.
+    let src = Path::new(&dir).join("src").display().to_string();
+    let run = |args: &[&str]| {
+        std::fs::write(Path::new(&dir).join("src/lib.rs"), lib).unwrap();
+        Command::new(BINARY)
+            .args(["--action", "fix", "--path", &src, "--compile-check"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    // Compiles when the parameter is skipped.
+    let output = run(&[]);
+    assert_eq!(output.stderr, []);
+    assert_eq!(output.status.code(), Some(0));
+    let output = run(&["--record-type", "Unit"]);
+    assert_eq!(output.stdout, []);
+    #[cfg(not(feature = "log"))]
+    {
+        assert_eq!(output.status.code(), Some(1));
+        let manifest = std::fs::canonicalize(Path::new(&dir).join("Cargo.toml")).unwrap();
+        let expected_stderr = format!(
+            "Error: Failed to compile {} after fixing:\n",
+            manifest.display()
+        );
+        let stderr = std::str::from_utf8(&output.stderr).unwrap();
+        assert!(stderr.starts_with(&expected_stderr));
+        assert!(stderr.contains("`Unit` doesn't implement `Debug`"));
+    }
+    #[cfg(feature = "log")]
+    assert_eq!(output.status.code(), Some(0));
+
+    // The fixed files must be checked in place.
+    for args in [["--dry-run"].as_slice(), &["--output-dir", "out"]] {
+        let output = run(args);
+        assert_eq!(output.status.code(), Some(2));
+        assert!(std::str::from_utf8(&output.stderr)
+            .unwrap()
+            .contains("cannot be used with"));
+    }
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn compat_error() {
+    let path = setup("fn main() {}");
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &path, "--compat", "0.1.0.0"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(output.stdout, []);
+    assert!(std::str::from_utf8(&output.stderr)
+        .unwrap()
+        .contains("Version has more than 3 components."));
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn fix_cfg() {
+    const GIVEN: &str =
+        "#[cfg(feature = \"x\")]\nfn f() {}\nimpl Unit {\n    #[cfg(unix)]\n    fn one() {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\n#[cfg(feature = \"x\")]\nfn f() {}\nimpl Unit {\n    #[tracing::instrument(level = \"trace\", skip())]\n    #[cfg(unix)]\n    fn one() {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\n#[cfg(feature = \"x\")]\nfn f() {}\nimpl Unit {\n    #[log_instrument::instrument]\n    #[cfg(unix)]\n    fn one() {}\n}";
+    fix(GIVEN, EXPECTED, None);
+}
+
+#[test]
+fn fix_place_below() {
+    const GIVEN: &str = "#[cfg(feature = \"x\")]\n#[inline]\nfn f() {}\nimpl Unit {\n    #[cfg(unix)]\n    fn one() {}\n}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[cfg(feature = \"x\")]\n#[tracing::instrument(level = \"trace\", skip())]\n#[inline]\nfn f() {}\nimpl Unit {\n    #[cfg(unix)]\n    #[tracing::instrument(level = \"trace\", skip())]\n    fn one() {}\n}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[cfg(feature = \"x\")]\n#[log_instrument::instrument]\n#[inline]\nfn f() {}\nimpl Unit {\n    #[cfg(unix)]\n    #[log_instrument::instrument]\n    fn one() {}\n}";
+    fix_args(GIVEN, EXPECTED, &["--place-below", "cfg"]);
+}
+
+#[test]
+fn fix_license_header() {
+    const GIVEN: &str = "// Copyright 2023 Unit\n// SPDX-License-Identifier: Apache-2.0\n//\n// Licensed under the Apache License.\nfn main() {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "// Copyright 2023 Unit\n// SPDX-License-Identifier: Apache-2.0\n//\n// Licensed under the Apache License.\n#[tracing::instrument(level = \"trace\", skip())]\nfn main() {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "// Copyright 2023 Unit\n// SPDX-License-Identifier: Apache-2.0\n//\n// Licensed under the Apache License.\n#[log_instrument::instrument]\nfn main() {}";
+    fix(GIVEN, EXPECTED, None);
+}
+
+#[test]
+fn fix_skip_cfg() {
+    const GIVEN: &str = "#[cfg(windows)]\nfn one() {}\n#[cfg(unix)]\nfn two() {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[cfg(windows)]\nfn one() {}\n#[tracing::instrument(level = \"trace\", skip())]\n#[cfg(unix)]\nfn two() {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str =
+        "#[cfg(windows)]\nfn one() {}\n#[log_instrument::instrument]\n#[cfg(unix)]\nfn two() {}";
+    fix_args(GIVEN, EXPECTED, &["--skip-cfg", "windows"]);
+}
+
+#[test]
+fn fix_active_features() {
+    const GIVEN: &str = "fn one() {}\n#[cfg(feature = \"a\")]\nmod a {\n    fn two() {}\n}\n#[cfg(feature = \"b\")]\nmod b {\n    mod inner {\n        fn three() {}\n    }\n}";
+    #[cfg(not(feature = "log"))]
+    const ALL: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}\n#[cfg(feature = \"a\")]\nmod a {\n    #[tracing::instrument(level = \"trace\", skip())]\n    fn two() {}\n}\n#[cfg(feature = \"b\")]\nmod b {\n    mod inner {\n        #[tracing::instrument(level = \"trace\", skip())]\n        fn three() {}\n    }\n}";
+    #[cfg(feature = "log")]
+    const ALL: &str = "#[log_instrument::instrument]\nfn one() {}\n#[cfg(feature = \"a\")]\nmod a {\n    #[log_instrument::instrument]\n    fn two() {}\n}\n#[cfg(feature = \"b\")]\nmod b {\n    mod inner {\n        #[log_instrument::instrument]\n        fn three() {}\n    }\n}";
+    #[cfg(not(feature = "log"))]
+    const A: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}\n#[cfg(feature = \"a\")]\nmod a {\n    #[tracing::instrument(level = \"trace\", skip())]\n    fn two() {}\n}\n#[cfg(feature = \"b\")]\nmod b {\n    mod inner {\n        fn three() {}\n    }\n}";
+    #[cfg(feature = "log")]
+    const A: &str = "#[log_instrument::instrument]\nfn one() {}\n#[cfg(feature = \"a\")]\nmod a {\n    #[log_instrument::instrument]\n    fn two() {}\n}\n#[cfg(feature = \"b\")]\nmod b {\n    mod inner {\n        fn three() {}\n    }\n}";
+    #[cfg(not(feature = "log"))]
+    const NONE: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}\n#[cfg(feature = \"a\")]\nmod a {\n    fn two() {}\n}\n#[cfg(feature = \"b\")]\nmod b {\n    mod inner {\n        fn three() {}\n    }\n}";
+    #[cfg(feature = "log")]
+    const NONE: &str = "#[log_instrument::instrument]\nfn one() {}\n#[cfg(feature = \"a\")]\nmod a {\n    fn two() {}\n}\n#[cfg(feature = \"b\")]\nmod b {\n    mod inner {\n        fn three() {}\n    }\n}";
+    fix(GIVEN, ALL, None);
+    fix_args(GIVEN, ALL, &["--active-features", "a,b"]);
+    fix_args(GIVEN, A, &["--active-features", "a"]);
+    fix_args(GIVEN, NONE, &["--active-features"]);
+
+    // Functions left uninstrumented are not reported by `check`.
+    let path = setup(A);
+    let output = Command::new(BINARY)
+        .args([
+            "--action",
+            "check",
+            "--path",
+            &path,
+            "--active-features",
+            "a",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    assert_eq!(output.stderr, []);
+
+    // They are reported as skipped for the inactive feature.
+    let output = Command::new(BINARY)
+        .args([
+            "--action",
+            "check",
+            "--path",
+            &path,
+            "--active-features",
+            "a",
+            "--report-uninstrumentable",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        format!("{path}:11: skipped (inactive feature)\n")
+    );
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn fix_skip_cfg_test() {
+    const GIVEN: &str = "#[cfg(test)]\nfn helper() {}\n#[cfg(all(test, unix))]\nfn unix_helper() {}\n#[cfg(not(test))]\nfn one() {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[cfg(test)]\nfn helper() {}\n#[cfg(all(test, unix))]\nfn unix_helper() {}\n#[tracing::instrument(level = \"trace\", skip())]\n#[cfg(not(test))]\nfn one() {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[cfg(test)]\nfn helper() {}\n#[cfg(all(test, unix))]\nfn unix_helper() {}\n#[log_instrument::instrument]\n#[cfg(not(test))]\nfn one() {}";
+    fix_args(GIVEN, EXPECTED, &["--skip-cfg-test"]);
+}
+
+#[test]
+fn check_one() {
+    const GIVEN: &str = "fn main() { }";
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let expected_stdout = format!("Missing instrumentation for `main` at {path}:1:0.\n");
+    assert_eq!(output.stdout, expected_stdout.as_bytes());
+    assert_eq!(output.stderr, []);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn check_two() {
+    #[cfg(not(feature = "log"))]
+    const GIVEN: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn main() { }\n#[test]\nfn my_test() { }";
+    #[cfg(feature = "log")]
+    const GIVEN: &str = "#[log_instrument::instrument]\nfn main() { }\n#[test]\nfn my_test() { }";
+    let path: String = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    assert_eq!(output.stderr, []);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn check_three() {
+    const GIVEN: &str = "impl Unit {\n    #[cfg_attr(feature = \"tracing\", tracing::instrument(level = \"trace\", skip()))]\n    fn one() {}\n}";
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args([
+            "--action",
+            "check",
+            "--path",
+            &path,
+            "--cfg-attr",
+            "feature = \"tracing\"",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "");
+    assert_eq!(std::str::from_utf8(&output.stderr).unwrap(), "");
+    assert_eq!(output.status.code(), Some(0));
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn check_nested() {
+    // Functions nested within a function missing instrumentation are also checked.
+    const GIVEN: &str = "fn outer() {\n    fn inner() {}\n}";
+    let path = setup(GIVEN);
+    let baseline = format!("{path}.baseline");
+    std::fs::write(&baseline, "").unwrap();
+    let output = Command::new(BINARY)
+        .args([
+            "--action",
+            "check",
+            "--path",
+            &path,
+            "--baseline",
+            &baseline,
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        format!("Missing instrumentation for `outer` at {path}:1:0.\nMissing instrumentation for `inner` at {path}:2:4.\n")
+    );
+    assert_eq!(output.stderr, []);
+    remove_file(path).unwrap();
+    remove_file(baseline).unwrap();
+}
+
+#[test]
+fn check_compat() {
+    // Before 0.8.0 messages do not name the function and functions nested within a function
+    // missing instrumentation are not checked.
+    const GIVEN: &str = "fn outer() {\n    fn inner() {}\n}";
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path, "--compat", "0.7"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        format!("Missing instrumentation at {path}:1:0.\n")
+    );
+    assert_eq!(output.stderr, []);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn check_commented_instrument() {
+    const GIVEN: &str =
+        "// #[tracing::instrument(level = \"trace\", skip())]\n#[inline]\nfn one() {}";
+    let path = setup(GIVEN);
+
+    // Without `--warn-commented-instrument`
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(output.stderr, []);
+
+    // With `--warn-commented-instrument`
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .arg("--warn-commented-instrument")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        format!("Missing instrumentation for `one` at {path}:2:0.\n")
+    );
+    assert_eq!(
+        std::str::from_utf8(&output.stderr).unwrap(),
+        format!("Warning: Instrumentation for `one` is commented out at {path}:2:0.\n")
+    );
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn check_cfg_attr() {
+    const GIVEN: &str = "#[cfg_attr(feature = \"tracing\", tracing::instrument(level = \"trace\", skip()))]\nfn one() { }\n#[cfg_attr(feature = \"tracing\", cfg_attr(test, tracing::instrument))]\nfn two() { }";
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    assert_eq!(output.stderr, []);
+    remove_file(path).unwrap();
+
+    // A `cfg_attr` predicate named `instrument` is not instrumentation.
+    const PREDICATE: &str = "#[cfg_attr(instrument, inline)]\nfn one() { }";
+    let path = setup(PREDICATE);
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let expected_stdout = format!("Missing instrumentation for `one` at {path}:1:0.\n");
+    assert_eq!(output.stdout, expected_stdout.as_bytes());
+    assert_eq!(output.stderr, []);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn check_instrument_forms() {
+    const GIVEN: &str = "#[instrument]\nfn one() {}\n#[instrument(skip_all)]\nfn two() {}\n#[tracing::instrument(level=\"info\")]\nfn three() {}\n#[tracing::instrument(\n    name = \"x\",\n    skip_all,\n)]\nfn four() {}\n#[tracing::instrument(name = \"x\", skip_all)]\nfn five() {}\n#[::tracing::instrument]\nfn six() {}\nimpl Unit {\n    #[instrument(level = \"debug\", ret)]\n    fn seven(&self) {}\n}";
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "");
+    assert_eq!(output.status.code(), Some(0));
+    remove_file(path).unwrap();
+
+    // Existing instrumentation is not duplicated.
+    fix(GIVEN, GIVEN, None);
+}
+
+#[test]
+fn check_leading_colon() {
+    const GIVEN: &str = "#[::tracing::instrument]\nfn one() {}\n#[cfg_attr(feature = \"tracing\", ::tracing::instrument(level = \"debug\"))]\nfn two() {}";
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "");
+    assert_eq!(output.status.code(), Some(0));
+    remove_file(path).unwrap();
+
+    fix(GIVEN, GIVEN, None);
+    strip(GIVEN, "fn one() {}\nfn two() {}");
+}
+
+#[test]
+fn check_expect_missing() {
+    // Marked
+    const MARKED: &str = "// clippy-tracing:expect-missing: called in a hot loop\nfn main() { }";
+    let path = setup(MARKED);
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    assert_eq!(output.stderr, []);
+    remove_file(path).unwrap();
+
+    // Unmarked
+    const UNMARKED: &str = "// clippy-tracing:expect-missing\nfn main() { }\n\nfn add() { }";
+    let path = setup(UNMARKED);
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let expected_stdout = format!("Missing instrumentation for `add` at {path}:4:0.\n");
+    assert_eq!(output.stdout, expected_stdout.as_bytes());
+    assert_eq!(output.stderr, []);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn check_report_uninstrumentable() {
+    const GIVEN: &str = "const fn one() {}\n#[test]\nfn two() {}\n#[clippy_tracing_attributes::clippy_tracing_skip]\nfn three() {}\nfn four() {}";
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .arg("--report-uninstrumentable")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let expected_stdout = format!("{path}:1: skipped (const)\n{path}:2: skipped (test)\n{path}:4: skipped (skip)\nMissing instrumentation for `four` at {path}:6:0.\n");
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        expected_stdout
+    );
+    assert_eq!(output.stderr, []);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn check_explain() {
+    const GIVEN: &str = "fn one() {}\nconst fn two() {}\n#[test]\nfn three() {}";
+    let path = setup(GIVEN);
+    let baseline = format!("{path}.baseline");
+    std::fs::write(&baseline, "").unwrap();
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path, "--explain"])
+        .args(["--baseline", &baseline])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        format!("Missing instrumentation for `one` at {path}:1:0. Flagged as not instrumented, not a test, not const, not skip-attributed.\n")
+    );
+    assert_eq!(output.stderr, []);
+
+    // Options which skip further functions are included.
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path, "--explain"])
+        .args([
+            "--baseline",
+            &baseline,
+            "--include-const",
+            "--skip-deprecated",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        format!("Missing instrumentation for `one` at {path}:1:0. Flagged as not instrumented, not a test, not const, not skip-attributed, not deprecated.\nMissing instrumentation for `two` at {path}:2:0. Flagged as not instrumented, not a test, const included, not skip-attributed, not deprecated.\n")
+    );
+    remove_file(path).unwrap();
+    remove_file(baseline).unwrap();
+}
+
+#[test]
+fn check_baseline() {
+    const GIVEN: &str = "fn main() { }\nfn add(lhs: i32, rhs: i32) -> i32 {\n    lhs + rhs\n}";
+    let path = setup(GIVEN);
+    let name = Path::new(&path).file_name().unwrap().to_str().unwrap();
+    let baseline = temp_path(&format!("{}.txt", uuid::Uuid::new_v4()));
+
+    // Write baseline
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .args(["--baseline", &baseline, "--write-baseline"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    assert_eq!(output.stderr, []);
+    check_file(&format!("{name}:main\n{name}:add\n"), &baseline);
+
+    // Check
+    let output = Command::new(BINARY)
+        .args([
+            "--action",
+            "check",
+            "--path",
+            &path,
+            "--baseline",
+            &baseline,
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    assert_eq!(output.stderr, []);
+
+    // Check with new missing instrumentation
+    remove_file(&path).unwrap();
+    let given = format!("{GIVEN}\nfn sub(lhs: i32, rhs: i32) -> i32 {{\n    lhs - rhs\n}}");
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    file.write_all(given.as_bytes()).unwrap();
+    let output = Command::new(BINARY)
+        .args([
+            "--action",
+            "check",
+            "--path",
+            &path,
+            "--baseline",
+            &baseline,
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let expected_stdout = format!("Missing instrumentation for `sub` at {path}:5:0.\n");
+    assert_eq!(output.stdout, expected_stdout.as_bytes());
+    assert_eq!(output.stderr, []);
+
+    // Baselined missing instrumentation does not hide other problems with the function.
+    std::fs::write(
+        &path,
+        "#[tracing::instrument(level = \"debug\")]\nfn main() { }",
+    )
+    .unwrap();
+    let check_level = || {
+        Command::new(BINARY)
+            .args(["--action", "check", "--path", &path])
+            .args(["--baseline", &baseline, "--enforce-level", "trace"])
+            .output()
+            .unwrap()
+    };
+    let output = check_level();
+    assert_eq!(output.status.code(), Some(2));
+    let expected_stdout = format!("Incorrect instrumentation level for `main` at {path}:1:0, found `debug` expected `trace`.\n");
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        expected_stdout
+    );
+    std::fs::write(&baseline, format!("{name}:main:level\n")).unwrap();
+    let output = check_level();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+
+    remove_file(path).unwrap();
+    remove_file(baseline).unwrap();
+}
+
+#[test]
+fn check_baseline_relative() {
+    let files = [(String::from("src/lib.rs"), String::from("fn one() {}"))];
+    let dir = setup_dir(&files);
+    let baseline = format!("{dir}.txt");
+
+    // Keys are relative to `--path`.
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &dir])
+        .args(["--baseline", &baseline, "--write-baseline"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    check_file("src/lib.rs:one\n", &baseline);
+
+    // The same keys match however `--path` is spelled.
+    for path in [format!("{dir}/."), String::from(".")] {
+        let output = Command::new(BINARY)
+            .args(["--action", "check", "--path", &path])
+            .args(["--baseline", &baseline])
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(output.stdout, []);
+    }
+
+    remove_file(baseline).unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn check_baseline_same_name() {
+    const GIVEN: &str = "impl One {\n    fn new() {}\n}\nimpl Two {\n    fn new() {}\n}\nimpl Default for Two {\n    fn default() -> Self {\n        fn new() {}\n        Self\n    }\n}";
+    let path = setup(GIVEN);
+    let name = Path::new(&path).file_name().unwrap().to_str().unwrap();
+    let baseline = temp_path(&format!("{}.txt", uuid::Uuid::new_v4()));
+
+    // Each function has a distinct key.
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .args(["--baseline", &baseline, "--write-baseline"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    check_file(
+        &format!("{name}:One::new\n{name}:Two::new\n{name}:<Two as Default>::default\n{name}:<Two as Default>::default::new\n"),
+        &baseline,
+    );
+
+    // Baselining one `new` does not hide the others.
+    std::fs::write(
+        &baseline,
+        format!("{name}:One::new\n{name}:<Two as Default>::default\n"),
+    )
+    .unwrap();
+    let output = Command::new(BINARY)
+        .args([
+            "--action",
+            "check",
+            "--path",
+            &path,
+            "--baseline",
+            &baseline,
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let expected_stdout = format!("Missing instrumentation for `new` at {path}:5:4.\nMissing instrumentation for `new` at {path}:9:8.\n");
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        expected_stdout
+    );
+    assert_eq!(output.stderr, []);
+
+    remove_file(path).unwrap();
+    remove_file(baseline).unwrap();
+}
+
+#[test]
+fn check_report_file() {
+    const GIVEN: &str = "fn main() { }";
+    let path = setup(GIVEN);
+    let report = temp_path(&format!("{}.txt", uuid::Uuid::new_v4()));
+
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .args(["--report-file", &report])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let expected = format!("Missing instrumentation for `main` at {path}:1:0.\n");
+    assert_eq!(output.stdout, expected.as_bytes());
+    assert_eq!(output.stderr, []);
+    check_file(&expected, &report);
+
+    remove_file(path).unwrap();
+    remove_file(report).unwrap();
+}
+
+#[test]
+fn check_enforce_level() {
+    const GIVEN: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}\n#[tracing::instrument(level = \"debug\", skip())]\nfn two() {}";
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args([
+            "--action",
+            "check",
+            "--path",
+            &path,
+            "--enforce-level",
+            "trace",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let expected_stdout =
+        format!("Incorrect instrumentation level for `two` at {path}:3:0, found `debug` expected `trace`.\n");
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        expected_stdout
+    );
+    assert_eq!(output.stderr, []);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn check_require_skip() {
+    const GIVEN: &str = "#[tracing::instrument(skip(x))]\nfn one(x: u8) {}\n#[instrument(level = \"debug\", skip_all)]\nfn two(y: u8) {}\n#[tracing::instrument(level = \"trace\", fields(skip = 1))]\nfn three(z: u8) {}";
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path, "--require-skip"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let expected_stdout =
+        format!("Missing `skip` or `skip_all` in instrumentation for `three` at {path}:5:0.\n");
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        expected_stdout
+    );
+    assert_eq!(output.stderr, []);
+
+    // Without the flag instrumentation without `skip` passes.
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn strip_one() {
+    #[cfg(not(feature = "log"))]
+    const GIVEN: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn main() { }";
+    #[cfg(feature = "log")]
+    const GIVEN: &str = "#[log_instrument::instrument]\nfn main() { }";
+    const EXPECTED: &str = "fn main() { }";
+    strip(GIVEN, EXPECTED);
+}
+
+#[test]
+fn strip_two() {
+    #[cfg(not(feature = "log"))]
+    const GIVEN: &str =
+        "#[tracing::instrument(    \nlevel = \"trace\",\n    skip()\n)]\nfn main() { }";
+    #[cfg(feature = "log")]
+    const GIVEN: &str = "#[log_instrument::instrument]\nfn main() { }";
+    const EXPECTED: &str = "fn main() { }";
+    strip(GIVEN, EXPECTED);
+}
+
+#[test]
+fn strip_three() {
+    const EXPECTED: &str = "impl Unit {\n    fn one() {}\n}";
+    #[cfg(not(feature = "log"))]
+    const GIVEN: &str =
+        "impl Unit {\n    #[tracing::instrument(level = \"trace\", skip())]\n    fn one() {}\n}";
+    #[cfg(feature = "log")]
+    const GIVEN: &str = "impl Unit {\n    #[log_instrument::instrument]\n    fn one() {}\n}";
+    strip(GIVEN, EXPECTED);
+}
+
+#[test]
+fn strip_level() {
+    const GIVEN: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}\n#[tracing::instrument(level = \"debug\", skip())]\nfn two() {}\nimpl Unit {\n    #[instrument(level = Level::TRACE)]\n    fn three() {}\n}\n#[instrument]\nfn four() {}";
+    const TRACE: &str = "fn one() {}\n#[tracing::instrument(level = \"debug\", skip())]\nfn two() {}\nimpl Unit {\n    fn three() {}\n}\n#[instrument]\nfn four() {}";
+    const INFO: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}\n#[tracing::instrument(level = \"debug\", skip())]\nfn two() {}\nimpl Unit {\n    #[instrument(level = Level::TRACE)]\n    fn three() {}\n}\nfn four() {}";
+    strip_args(GIVEN, TRACE, &["--strip-level", "trace"]);
+    strip_args(GIVEN, INFO, &["--strip-level", "info"]);
+    strip_args(GIVEN, GIVEN, &["--strip-level", "error"]);
+}
+
+#[test]
+fn strip_clean_imports() {
+    const GIVEN: &str = "use tracing::instrument;\n#[instrument]\nfn one() {}";
+    const EXPECTED: &str = "fn one() {}";
+
+    // Without `--clean-imports`
+    strip(GIVEN, "use tracing::instrument;\nfn one() {}");
+
+    // With `--clean-imports`
+    strip_args(GIVEN, EXPECTED, &["--clean-imports"]);
+
+    // Imports which are still used are kept.
+    const USED: &str = "use tracing::instrument as trace;\n#[trace]\nfn one() {}";
+    strip_args(USED, USED, &["--clean-imports"]);
+    const GROUP: &str = "use tracing::{info, instrument};\n#[instrument]\nfn one() {}";
+    strip_args(
+        GROUP,
+        "use tracing::{info, instrument};\nfn one() {}",
+        &["--clean-imports"],
+    );
+}
+
+#[test]
+fn fix_ensure_import() {
+    const GIVEN: &str = "#![allow(unused)]\nfn one() {}\nfn two() {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#![allow(unused)]\nuse tracing::instrument;\n#[instrument(level = \"trace\", skip())]\nfn one() {}\n#[instrument(level = \"trace\", skip())]\nfn two() {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#![allow(unused)]\nuse log_instrument::instrument;\n#[instrument]\nfn one() {}\n#[instrument]\nfn two() {}";
+    fix_args(GIVEN, EXPECTED, &["--suffix", "", "--ensure-import"]);
+    // The import is added once.
+    fix_args(EXPECTED, EXPECTED, &["--suffix", "", "--ensure-import"]);
+
+    // Existing imports within groups are not duplicated.
+    const GROUP: &str = "use tracing::{info, instrument};\nfn one() {}";
+    #[cfg(not(feature = "log"))]
+    const GROUP_EXPECTED: &str =
+        "use tracing::{info, instrument};\n#[instrument(level = \"trace\", skip())]\nfn one() {}";
+    #[cfg(feature = "log")]
+    const GROUP_EXPECTED: &str = "use tracing::{info, instrument};\n#[instrument]\nfn one() {}";
+    fix_args(GROUP, GROUP_EXPECTED, &["--suffix", "", "--ensure-import"]);
+
+    // The import is placed below a shebang.
+    const SHEBANG: &str = "#!/usr/bin/env run-cargo-script\nfn main() {}";
+    #[cfg(not(feature = "log"))]
+    const SHEBANG_EXPECTED: &str = "#!/usr/bin/env run-cargo-script\nuse tracing::instrument;\n#[instrument(level = \"trace\", skip())]\nfn main() {}";
+    #[cfg(feature = "log")]
+    const SHEBANG_EXPECTED: &str = "#!/usr/bin/env run-cargo-script\nuse log_instrument::instrument;\n#[instrument]\nfn main() {}";
+    fix_args(
+        SHEBANG,
+        SHEBANG_EXPECTED,
+        &["--suffix", "", "--ensure-import"],
+    );
+}
+
+#[test]
+fn dedupe() {
+    const GIVEN: &str = "#[tracing::instrument(level = \"trace\", skip())]\n#[inline]\n#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}\nimpl Unit {\n    #[instrument]\n    #[instrument]\n    fn two() {}\n}\n#[instrument]\nfn three() {}";
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\n#[inline]\nfn one() {}\nimpl Unit {\n    #[instrument]\n    fn two() {}\n}\n#[instrument]\nfn three() {}";
+    let path = setup(GIVEN);
+    let output = Command::new(BINARY)
+        .args(["--action", "dedupe", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    assert_eq!(output.stderr, []);
+    check_file(EXPECTED, &path);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn strip_dry_run() {
+    let files = [
+        (
+            String::from("one.rs"),
+            String::from(
+                "#[instrument]\nfn one() {}\nimpl Unit {\n    #[instrument]\n    fn two() {}\n}",
+            ),
+        ),
+        (
+            String::from("two.rs"),
+            String::from("#[instrument]\nfn three() {}"),
+        ),
+        (String::from("three.rs"), String::from("fn four() {}")),
+    ];
+    let dir = setup_dir(&files);
+    let output = Command::new(BINARY)
+        .args(["--action", "strip", "--path", &dir, "--dry-run"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        "Would remove 3 instrument attributes from 2 files.\n"
+    );
+    assert_eq!(output.stderr, []);
+    for (name, text) in files {
+        check_file(&text, &Path::new(&dir).join(name).display().to_string());
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn include_target() {
+    let files = [
+        (
+            String::from("target/debug/build/out.rs"),
+            String::from("fn one() {}"),
+        ),
+        (String::from(".git/two.rs"), String::from("fn two() {}")),
+    ];
+    let dir = setup_dir(&files);
+
+    // Without `--include-target`
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &dir])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    assert_eq!(output.stderr, []);
+
+    // With `--include-target`
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &dir, "--include-target"])
+        .args(["--baseline", &format!("{dir}.txt"), "--write-baseline"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let baseline = std::fs::read_to_string(format!("{dir}.txt")).unwrap();
+    let mut keys = baseline.lines().collect::<Vec<_>>();
+    keys.sort_unstable();
+    assert_eq!(keys, [".git/two.rs:two", "target/debug/build/out.rs:one"]);
+
+    remove_file(format!("{dir}.txt")).unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn skip_out_dir() {
+    let files = [
+        (
+            String::from("target/debug/build/one-0123/out/generated.rs"),
+            String::from("fn generated() {}"),
+        ),
+        (
+            String::from("gen/generated.rs"),
+            String::from("fn generated() {}"),
+        ),
+        (String::from("src/two.rs"), String::from("fn two() {}")),
+    ];
+    let dir = setup_dir(&files);
+
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &dir])
+        .args(["--include-target", "--skip-out-dir"])
+        .env("OUT_DIR", Path::new(&dir).join("gen"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let path = Path::new(&dir).join("src").join("two.rs");
+    let expected_stdout = format!(
+        "Missing instrumentation for `two` at {}:1:0.\n",
+        path.display()
+    );
+    assert_eq!(output.stdout, expected_stdout.as_bytes());
+    assert_eq!(output.stderr, []);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn include_build_rs() {
+    let files = [(String::from("build.rs"), String::from("fn main() {}"))];
+    let dir = setup_dir(&files);
+    let path = Path::new(&dir).join("build.rs");
+
+    // Build scripts are skipped by default.
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &dir])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    assert_eq!(output.stderr, []);
+
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &dir, "--include-build-rs"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let expected_stdout = format!(
+        "Missing instrumentation for `main` at {}:1:0.\n",
+        path.display()
+    );
+    assert_eq!(output.stdout, expected_stdout.as_bytes());
+    let expected_stderr = format!(
+        "Warning: Processing build script {}, build scripts usually run before tracing is set up.\n",
+        path.display()
+    );
+    assert_eq!(output.stderr, expected_stderr.as_bytes());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn exclude_marker() {
+    let files = [
+        (String::from("src/gen/.notrace"), String::new()),
+        (String::from("src/gen/one.rs"), String::from("fn one() {}")),
+        (
+            String::from("src/gen/inner/two.rs"),
+            String::from("fn two() {}"),
+        ),
+        (String::from("src/three.rs"), String::from("fn three() {}")),
+    ];
+    let dir = setup_dir(&files);
+    // All problems are reported with an empty baseline.
+    let baseline = Path::new(&dir).join("baseline");
+    std::fs::write(&baseline, "").unwrap();
+
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &dir])
+        .args(["--exclude-marker", ".notrace", "--baseline"])
+        .arg(&baseline)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let path = Path::new(&dir).join("src").join("three.rs");
+    let expected_stdout = format!(
+        "Missing instrumentation for `three` at {}:1:0.\n",
+        path.display()
+    );
+    assert_eq!(output.stdout, expected_stdout.as_bytes());
+    assert_eq!(output.stderr, []);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn error_on_empty() {
+    let files = [(String::from("gen/one.rs"), String::from("fn one() {}"))];
+    let dir = setup_dir(&files);
+
+    // Without `--error-on-empty`
     let output = Command::new(BINARY)
-        .args(["--action", "strip", "--path", &path])
+        .args(["--action", "check", "--path", &dir, "--exclude", "gen"])
         .output()
         .unwrap();
     assert_eq!(output.status.code(), Some(0));
     assert_eq!(output.stdout, []);
     assert_eq!(output.stderr, []);
-    check_file(expected, &path);
-    remove_file(path).unwrap();
+
+    // With `--error-on-empty`
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &dir, "--exclude", "gen"])
+        .arg("--error-on-empty")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, []);
+    assert_eq!(
+        std::str::from_utf8(&output.stderr).unwrap(),
+        format!("Error: Found no files to process in {dir}.\n")
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
 }
 
 #[test]
-fn exec_error() {
-    // Create file path for a file that doesn't exist.
-    let id = uuid::Uuid::new_v4();
-    let path = format!("/tmp/{id}.rs");
+fn exclude_nested() {
+    let files = [
+        (String::from("src/gen/one.rs"), String::from("fn one() {}")),
+        (String::from("src/two.rs"), String::from("fn two() {}")),
+    ];
+    let dir = setup_dir(&files);
 
+    // Excludes use `/` separators on all platforms.
     let output = Command::new(BINARY)
-        .args(["--action", "check", "--path", &path])
+        .args(["--action", "check", "--path", &dir, "--exclude", "src/gen"])
         .output()
         .unwrap();
-    assert_eq!(output.status.code(), Some(1));
-    assert_eq!(output.stdout, []);
-    let expected_stderr = format!("Error: Failed to read entry in file path: IO error for operation on {path}: No such file or directory (os error 2)\n");
-    assert_eq!(output.stderr, expected_stderr.as_bytes());
+    assert_eq!(output.status.code(), Some(2));
+    let path = Path::new(&dir).join("src").join("two.rs");
+    let expected_stdout = format!(
+        "Missing instrumentation for `two` at {}:1:0.\n",
+        path.display()
+    );
+    assert_eq!(output.stdout, expected_stdout.as_bytes());
+    assert_eq!(output.stderr, []);
+
+    std::fs::remove_dir_all(dir).unwrap();
 }
 
 #[test]
-fn fix_one() {
-    const GIVEN: &str = "fn main() { }\nfn add(lhs: i32, rhs: i32) {\n    lhs + rhs\n}";
-    #[cfg(not(feature = "log"))]
-    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn main() { }\n#[tracing::instrument(level = \"trace\", skip(lhs, rhs))]\nfn add(lhs: i32, rhs: i32) {\n    lhs + rhs\n}";
-    #[cfg(feature = "log")]
-    const EXPECTED: &str = "#[log_instrument::instrument]\nfn main() { }\n#[log_instrument::instrument]\nfn add(lhs: i32, rhs: i32) {\n    lhs + rhs\n}";
-    fix(GIVEN, EXPECTED, None);
+fn modified_since() {
+    let files = [
+        (String::from("old.rs"), String::from("fn old() {}")),
+        (String::from("new.rs"), String::from("fn new() {}")),
+        (String::from("baseline"), String::new()),
+    ];
+    let dir = setup_dir(&files);
+    // 2001-09-09T01:46:40Z
+    let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+    std::fs::File::options()
+        .write(true)
+        .open(Path::new(&dir).join("old.rs"))
+        .unwrap()
+        .set_modified(old)
+        .unwrap();
+
+    let path = Path::new(&dir).join("new.rs");
+    let expected_stdout = format!(
+        "Missing instrumentation for `new` at {}:1:0.\n",
+        path.display()
+    );
+    for since in ["1h", "2001-09-09T02:46:41+01:00"] {
+        let output = Command::new(BINARY)
+            .args(["--action", "check", "--path", &dir, "--baseline"])
+            .arg(Path::new(&dir).join("baseline"))
+            .args(["--modified-since", since])
+            .output()
+            .unwrap();
+        assert_eq!(std::str::from_utf8(&output.stderr).unwrap(), "");
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(
+            std::str::from_utf8(&output.stdout).unwrap(),
+            expected_stdout
+        );
+    }
+    std::fs::remove_dir_all(dir).unwrap();
 }
 
 #[test]
-fn fix_two() {
-    const GIVEN: &str = "impl Unit {\n    fn one() {}\n}";
+fn markdown() {
+    const GIVEN: &str = "# Example\n\n```toml\n[dependencies]\n```\n\n```rust\nfn one() {}\n```\n";
     #[cfg(not(feature = "log"))]
-    const EXPECTED: &str =
-        "impl Unit {\n    #[tracing::instrument(level = \"trace\", skip())]\n    fn one() {}\n}";
+    const EXPECTED: &str = "# Example\n\n```toml\n[dependencies]\n```\n\n```rust\n#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}\n```\n";
     #[cfg(feature = "log")]
-    const EXPECTED: &str = "impl Unit {\n    #[log_instrument::instrument]\n    fn one() {}\n}";
-    fix(GIVEN, EXPECTED, None);
+    const EXPECTED: &str = "# Example\n\n```toml\n[dependencies]\n```\n\n```rust\n#[log_instrument::instrument]\nfn one() {}\n```\n";
+    let files = [(String::from("README.md"), String::from(GIVEN))];
+    let dir = setup_dir(&files);
+    let path = Path::new(&dir).join("README.md");
+
+    // Lines are reported within the Markdown file.
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &dir, "--markdown"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let expected_stdout = format!(
+        "Missing instrumentation for `one` at {}:8:0.\n",
+        path.display()
+    );
+    assert_eq!(output.stdout, expected_stdout.as_bytes());
+    assert_eq!(output.stderr, []);
+
+    // Markdown files are not processed by default.
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &dir])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    check_file(GIVEN, &path.display().to_string());
+
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &dir, "--markdown"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    check_file(EXPECTED, &path.display().to_string());
+
+    let output = Command::new(BINARY)
+        .args(["--action", "strip", "--path", &dir, "--markdown"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    check_file(GIVEN, &path.display().to_string());
+
+    std::fs::remove_dir_all(dir).unwrap();
 }
 
 #[test]
-fn fix_three() {
-    const GIVEN: &str = "impl Unit {\n    fn one() {}\n}";
+fn only_changed_functions() {
+    const GIVEN: &str = "fn one() {\n    let x = 1;\n}\nfn two() {\n    let x = 3;\n}";
     #[cfg(not(feature = "log"))]
-    const EXPECTED: &str =
-        "impl Unit {\n    #[cfg_attr(feature = \"tracing\", tracing::instrument(level = \"trace\", skip()))]\n    fn one() {}\n}";
+    const EXPECTED: &str = "fn one() {\n    let x = 1;\n}\n#[tracing::instrument(level = \"trace\", skip())]\nfn two() {\n    let x = 3;\n}";
     #[cfg(feature = "log")]
-    const EXPECTED: &str = "impl Unit {\n    #[cfg_attr(feature = \"tracing\", log_instrument::instrument)]\n    fn one() {}\n}";
-    fix(GIVEN, EXPECTED, Some("feature = \"tracing\""));
+    const EXPECTED: &str = "fn one() {\n    let x = 1;\n}\n#[log_instrument::instrument]\nfn two() {\n    let x = 3;\n}";
+    let files = [(
+        String::from("lib.rs"),
+        String::from("fn one() {\n    let x = 1;\n}\nfn two() {\n    let x = 2;\n}"),
+    )];
+    let dir = setup_dir(&files);
+    let git = |args: &[&str]| {
+        let output = Command::new("git")
+            .args([
+                "-C",
+                &dir,
+                "-c",
+                "user.name=test",
+                "-c",
+                "user.email=test@test",
+            ])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+    };
+    git(&["init", "--quiet"]);
+    git(&["add", "lib.rs"]);
+    git(&["commit", "--quiet", "--message", "init"]);
+    let path = Path::new(&dir).join("lib.rs");
+    std::fs::write(&path, GIVEN).unwrap();
+
+    // Only `two` is changed.
+    let output = Command::new(BINARY)
+        .args([
+            "--action",
+            "fix",
+            "--path",
+            &dir,
+            "--only-changed-functions",
+            "HEAD",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stderr, []);
+    check_file(EXPECTED, &path.display().to_string());
+
+    std::fs::remove_dir_all(dir).unwrap();
 }
 
 #[test]
-fn check_one() {
-    const GIVEN: &str = "fn main() { }";
-    let path = setup(GIVEN);
+fn collect_args_stats() {
+    let files = [
+        (
+            String::from("one.rs"),
+            String::from(
+                "fn one(a: u8, b: String) {}\nimpl Unit {\n    fn two(&self, c: u8, d: &str) {}\n}",
+            ),
+        ),
+        (
+            String::from("two.rs"),
+            String::from("#[instrument]\nfn three(e: std::vec::Vec<u8>, f: String, g: u8) {}"),
+        ),
+    ];
+    let dir = setup_dir(&files);
+    let baseline = Path::new(&dir).join("baseline");
+    std::fs::write(&baseline, "").unwrap();
     let output = Command::new(BINARY)
-        .args(["--action", "check", "--path", &path])
+        .args(["--action", "check", "--path", &dir, "--collect-args-stats"])
+        .arg("--baseline")
+        .arg(&baseline)
         .output()
         .unwrap();
     assert_eq!(output.status.code(), Some(2));
-    let expected_stdout = format!("Missing instrumentation at {path}:1:0.\n");
-    assert_eq!(output.stdout, expected_stdout.as_bytes());
+    // References are not counted as `--record-type` does not match them.
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    assert!(stdout.starts_with("3 u8\n2 String\n1 Vec\n"), "{stdout}");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn by_file() {
+    let files = [
+        (
+            String::from("one.rs"),
+            String::from("fn one() {}\n#[instrument]\nfn two() {}"),
+        ),
+        (
+            String::from("two.rs"),
+            String::from("fn three() {}\nfn four() {}\n#[test]\nfn five() {}"),
+        ),
+        (String::from("three.rs"), String::from("struct Unit;")),
+    ];
+    let dir = setup_dir(&files);
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &dir, "--by-file"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    // The breakdown is followed by the one finding reported without a baseline, files without
+    // functions are omitted.
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let one = Path::new(&dir).join("one.rs");
+    let two = Path::new(&dir).join("two.rs");
+    let expected = format!(
+        "{}: 0 instrumented, 2 missing\n{}: 1 instrumented, 1 missing\n",
+        two.display(),
+        one.display()
+    );
+    assert!(stdout.starts_with(&expected), "{stdout}");
+    assert_eq!(stdout.lines().count(), 3);
     assert_eq!(output.stderr, []);
-    remove_file(path).unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
 }
 
 #[test]
-fn check_two() {
-    #[cfg(not(feature = "log"))]
-    const GIVEN: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn main() { }\n#[test]\nfn my_test() { }";
-    #[cfg(feature = "log")]
-    const GIVEN: &str = "#[log_instrument::instrument]\nfn main() { }\n#[test]\nfn my_test() { }";
-    let path: String = setup(GIVEN);
+fn summary_json() {
+    let files = [
+        (
+            String::from("one.rs"),
+            String::from("fn one() {}\n#[instrument]\nfn two() {}"),
+        ),
+        (
+            String::from("two.rs"),
+            String::from("fn three() {}\nconst fn four() {}"),
+        ),
+    ];
+    let dir = setup_dir(&files);
     let output = Command::new(BINARY)
-        .args(["--action", "check", "--path", &path])
+        .args(["--action", "check", "--path", &dir, "--summary-json"])
         .output()
         .unwrap();
-    assert_eq!(output.status.code(), Some(0));
-    assert_eq!(output.stdout, []);
+    assert_eq!(output.status.code(), Some(2));
+    // Only one finding is reported without a baseline but all files are summarized.
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap().lines().count(),
+        1
+    );
+    assert_eq!(
+        std::str::from_utf8(&output.stderr).unwrap(),
+        "{\"files_scanned\": 2, \"functions_total\": 4, \"missing\": 2}\n"
+    );
+
+    let summary_file = Path::new(&dir).join("summary.json");
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &dir, "--summary-json"])
+        .arg("--summary-file")
+        .arg(&summary_file)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
     assert_eq!(output.stderr, []);
-    remove_file(path).unwrap();
+    check_file(
+        "{\"files_scanned\": 2, \"functions_total\": 4, \"missing\": 2}\n",
+        &summary_file.display().to_string(),
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
 }
 
 #[test]
-fn check_three() {
-    const GIVEN: &str = "impl Unit {\n    #[cfg_attr(feature = \"tracing\", tracing::instrument(level = \"trace\", skip()))]\n    fn one() {}\n}";
-    let path = setup(GIVEN);
+fn progress() {
+    let files = [
+        (String::from("one.rs"), String::from("const fn one() {}")),
+        (String::from("two.rs"), String::from("const fn two() {}")),
+    ];
+    let dir = setup_dir(&files);
+
+    // With `--quiet`
     let output = Command::new(BINARY)
-        .args([
-            "--action",
-            "check",
-            "--path",
-            &path,
-            "--cfg-attr",
-            "feature = \"tracing\"",
-        ])
+        .args(["--action", "fix", "--path", &dir, "--include-const"])
+        .args(["--progress", "--quiet"])
         .output()
         .unwrap();
-    assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "");
-    assert_eq!(std::str::from_utf8(&output.stderr).unwrap(), "");
     assert_eq!(output.status.code(), Some(0));
-    remove_file(path).unwrap();
+    assert_eq!(output.stdout, []);
+    assert_eq!(output.stderr, []);
+    let output = Command::new(BINARY)
+        .args(["--action", "strip", "--path", &dir])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+
+    // Without `--quiet`
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &dir, "--include-const"])
+        .arg("--progress")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, []);
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(stderr.contains("Processed 1/2 files.\n"));
+    assert!(stderr.contains("Processed 2/2 files.\n"));
+    assert!(stderr.contains("Warning: "));
+
+    std::fs::remove_dir_all(dir).unwrap();
 }
 
 #[test]
-fn strip_one() {
-    #[cfg(not(feature = "log"))]
-    const GIVEN: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn main() { }";
-    #[cfg(feature = "log")]
-    const GIVEN: &str = "#[log_instrument::instrument]\nfn main() { }";
-    const EXPECTED: &str = "fn main() { }";
-    strip(GIVEN, EXPECTED);
+fn threads_parity() {
+    let files = (0..20)
+        .map(|i| {
+            let text = format!("fn f{i}() {{}}\nimpl Unit {{\n    fn g{i}(&self) {{}}\n}}");
+            (format!("{i}.rs"), text)
+        })
+        .collect::<Vec<_>>();
+    let sequential = setup_dir(&files);
+    let pipelined = setup_dir(&files);
+    const THREADS: [&str; 4] = ["--read-threads", "2", "--parse-threads", "3"];
+
+    // Check
+    let run = |dir: &str, threads: &[&str]| {
+        let baseline = format!("{dir}.txt");
+        let output = Command::new(BINARY)
+            .args(["--action", "check", "--path", dir, "--baseline", &baseline])
+            .arg("--write-baseline")
+            .args(threads)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        let text = std::fs::read_to_string(&baseline).unwrap();
+        remove_file(baseline).unwrap();
+        text
+    };
+    assert_eq!(run(&sequential, &[]), run(&sequential, &THREADS));
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &sequential])
+        .output()
+        .unwrap();
+    let threaded_output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &sequential])
+        .args(THREADS)
+        .output()
+        .unwrap();
+    assert_eq!(output, threaded_output);
+
+    // Fix
+    for (dir, threads) in [(&sequential, &[][..]), (&pipelined, &THREADS[..])] {
+        let output = Command::new(BINARY)
+            .args(["--action", "fix", "--path", dir])
+            .args(threads)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(output.stdout, []);
+        assert_eq!(output.stderr, []);
+    }
+    for (name, _) in &files {
+        let expected = std::fs::read_to_string(format!("{sequential}/{name}")).unwrap();
+        check_file(&expected, &format!("{pipelined}/{name}"));
+    }
+
+    std::fs::remove_dir_all(sequential).unwrap();
+    std::fs::remove_dir_all(pipelined).unwrap();
 }
 
 #[test]
-fn strip_two() {
-    #[cfg(not(feature = "log"))]
-    const GIVEN: &str =
-        "#[tracing::instrument(    \nlevel = \"trace\",\n    skip()\n)]\nfn main() { }";
-    #[cfg(feature = "log")]
-    const GIVEN: &str = "#[log_instrument::instrument]\nfn main() { }";
-    const EXPECTED: &str = "fn main() { }";
-    strip(GIVEN, EXPECTED);
+fn threads_parity_varied() {
+    // Fragments covering the different kinds of functions, combined pseudo-randomly per file.
+    const FRAGMENTS: [&str; 10] = [
+        "fn free(a: u8, b: &str) -> u8 {\n    a\n}",
+        "impl Unit {\n    fn method(&self) {}\n    fn other(&mut self, x: i32) {}\n}",
+        "trait Trait {\n    fn required(&self);\n    fn provided(&self) {}\n}",
+        "mod inner {\n    fn nested() {\n        fn deeper() {}\n    }\n}",
+        "#[tracing::instrument(level = \"trace\", skip())]\nfn instrumented() {}",
+        "#[test]\nfn a_test() {}",
+        "const fn constant() -> u8 {\n    1\n}",
+        "/// Documented.\n#[inline]\nfn documented() {}",
+        "fn one() {} fn two() {}",
+        "struct Unit;",
+    ];
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let files = (0..64)
+        .map(|i| {
+            let fragments = (0..4)
+                .map(|_| {
+                    // Xorshift.
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    let index = usize::try_from(state % 10).unwrap();
+                    FRAGMENTS[index]
+                })
+                .collect::<Vec<_>>();
+            (format!("dir{}/{i}.rs", i % 4), fragments.join("\n"))
+        })
+        .collect::<Vec<_>>();
+    let sequential = setup_dir(&files);
+    let pipelined = setup_dir(&files);
+    const THREADS: [&str; 4] = ["--read-threads", "3", "--parse-threads", "4"];
+
+    // Check, the misses are sorted as their order is not part of the comparison.
+    let run = |dir: &str, threads: &[&str]| {
+        let baseline = format!("{dir}.txt");
+        let output = Command::new(BINARY)
+            .args(["--action", "check", "--path", dir, "--baseline", &baseline])
+            .arg("--write-baseline")
+            .args(threads)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        let text = std::fs::read_to_string(&baseline).unwrap();
+        remove_file(baseline).unwrap();
+        let mut misses = text.lines().map(String::from).collect::<Vec<_>>();
+        misses.sort();
+        misses
+    };
+    let misses = run(&sequential, &[]);
+    assert!(!misses.is_empty());
+    assert_eq!(misses, run(&sequential, &THREADS));
+
+    // Fix then strip
+    for action in ["fix", "strip"] {
+        for (dir, threads) in [(&sequential, &[][..]), (&pipelined, &THREADS[..])] {
+            let output = Command::new(BINARY)
+                .args(["--action", action, "--path", dir])
+                .args(threads)
+                .output()
+                .unwrap();
+            assert_eq!(std::str::from_utf8(&output.stderr).unwrap(), "");
+            assert_eq!(output.status.code(), Some(0));
+        }
+        for (name, _) in &files {
+            let expected = std::fs::read_to_string(format!("{sequential}/{name}")).unwrap();
+            check_file(&expected, &format!("{pipelined}/{name}"));
+        }
+    }
+
+    std::fs::remove_dir_all(sequential).unwrap();
+    std::fs::remove_dir_all(pipelined).unwrap();
 }
 
 #[test]
-fn strip_three() {
-    const EXPECTED: &str = "impl Unit {\n    fn one() {}\n}";
+fn shebang() {
+    const GIVEN: &str = "#!/usr/bin/env run-cargo-script\nfn main() {}\nfn add() {}";
     #[cfg(not(feature = "log"))]
-    const GIVEN: &str =
-        "impl Unit {\n    #[tracing::instrument(level = \"trace\", skip())]\n    fn one() {}\n}";
+    const EXPECTED: &str = "#!/usr/bin/env run-cargo-script\n#[tracing::instrument(level = \"trace\", skip())]\nfn main() {}\n#[tracing::instrument(level = \"trace\", skip())]\nfn add() {}";
     #[cfg(feature = "log")]
-    const GIVEN: &str = "impl Unit {\n    #[log_instrument::instrument]\n    fn one() {}\n}";
-    strip(GIVEN, EXPECTED);
+    const EXPECTED: &str = "#!/usr/bin/env run-cargo-script\n#[log_instrument::instrument]\nfn main() {}\n#[log_instrument::instrument]\nfn add() {}";
+    let path = setup(GIVEN);
+
+    // Check
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let expected_stdout = format!("Missing instrumentation for `add` at {path}:3:0.\n");
+    assert_eq!(output.stdout, expected_stdout.as_bytes());
+
+    // Fix
+    let output = Command::new(BINARY)
+        .args(["--action", "fix", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    check_file(EXPECTED, &path);
+
+    // Strip
+    let output = Command::new(BINARY)
+        .args(["--action", "strip", "--path", &path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    check_file(GIVEN, &path);
+    remove_file(path).unwrap();
 }
 
 #[test]
@@ -222,7 +3139,7 @@ mod tests {
         .output()
         .unwrap();
     assert_eq!(output.status.code(), Some(2));
-    let missing = format!("Missing instrumentation at {path}:9:4.\n");
+    let missing = format!("Missing instrumentation for `sub` at {path}:9:4.\n");
     assert_eq!(output.stdout, missing.as_bytes());
     assert_eq!(output.stderr, []);
     #[cfg(not(feature = "log"))]
@@ -323,7 +3240,7 @@ mod tests {
         .output()
         .unwrap();
     assert_eq!(output.status.code(), Some(2));
-    let missing = format!("Missing instrumentation at {path}:9:4.\n");
+    let missing = format!("Missing instrumentation for `sub` at {path}:9:4.\n");
     assert_eq!(output.stdout, missing.as_bytes());
     assert_eq!(output.stderr, []);
     #[cfg(not(feature = "log"))]
@@ -424,7 +3341,7 @@ mod tests {
         .output()
         .unwrap();
     assert_eq!(output.status.code(), Some(2));
-    let missing = format!("Missing instrumentation at {path}:9:4.\n");
+    let missing = format!("Missing instrumentation for `sub` at {path}:9:4.\n");
     assert_eq!(output.stdout, missing.as_bytes());
     assert_eq!(output.stderr, []);
     #[cfg(not(feature = "log"))]
@@ -505,3 +3422,183 @@ mod tests {
     assert_eq!(output.stderr, []);
     check_file(GIVEN, &path);
 }
+
+#[test]
+fn output_dir() {
+    const GIVEN: &str = "fn one() {}";
+    #[cfg(not(feature = "log"))]
+    const EXPECTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}";
+    #[cfg(feature = "log")]
+    const EXPECTED: &str = "#[log_instrument::instrument]\nfn one() {}";
+    let files = [
+        (String::from("src/inner/one.rs"), String::from(GIVEN)),
+        (String::from("src/two.rs"), String::from(EXPECTED)),
+    ];
+    let dir = setup_dir(&files);
+    // The output directory is outside the walked tree.
+    let output_dir = format!("{dir}.out");
+
+    let output = Command::new(BINARY)
+        .args([
+            "--action",
+            "fix",
+            "--path",
+            &dir,
+            "--output-dir",
+            &output_dir,
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stderr, []);
+    let relative = Path::new("src").join("inner").join("one.rs");
+    check_file(
+        GIVEN,
+        &Path::new(&dir).join(&relative).display().to_string(),
+    );
+    check_file(
+        EXPECTED,
+        &Path::new(&output_dir).join(&relative).display().to_string(),
+    );
+    // Unchanged files are copied.
+    check_file(
+        EXPECTED,
+        &Path::new(&output_dir)
+            .join("src")
+            .join("two.rs")
+            .display()
+            .to_string(),
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+    std::fs::remove_dir_all(output_dir).unwrap();
+
+    // An output directory within the walked tree is not walked, so a second run does not process
+    // the output of the first.
+    let dir = setup_dir(&files);
+    let output_dir = Path::new(&dir).join("out").display().to_string();
+    for _ in 0..2 {
+        let output = Command::new(BINARY)
+            .args([
+                "--action",
+                "fix",
+                "--path",
+                &dir,
+                "--output-dir",
+                &output_dir,
+            ])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(output.stderr, []);
+    }
+    check_file(
+        EXPECTED,
+        &Path::new(&output_dir).join(&relative).display().to_string(),
+    );
+    assert!(!Path::new(&output_dir).join("out").exists());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn skip_parse_errors() {
+    #[cfg(not(feature = "log"))]
+    const INSTRUMENTED: &str = "#[tracing::instrument(level = \"trace\", skip())]\nfn one() {}";
+    #[cfg(feature = "log")]
+    const INSTRUMENTED: &str = "#[log_instrument::instrument]\nfn one() {}";
+    // Runs `check` on the given files with an empty baseline so all files are processed,
+    // returning the exit code and stderr.
+    let check = |files: &[(String, String)], skip: bool| {
+        let dir = setup_dir(files);
+        let baseline = Path::new(&dir).join("baseline");
+        std::fs::write(&baseline, "").unwrap();
+        let output = Command::new(BINARY)
+            .args(["--action", "check", "--path", &dir, "--baseline"])
+            .arg(&baseline)
+            .args(skip.then_some("--skip-parse-errors"))
+            .output()
+            .unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+        (
+            output.status.code(),
+            String::from_utf8(output.stderr).unwrap(),
+        )
+    };
+    let broken = (String::from("broken.rs"), String::from("fn broken("));
+    let covered = (String::from("one.rs"), String::from(INSTRUMENTED));
+    let missing = (String::from("two.rs"), String::from("fn two() {}"));
+
+    // Without misses.
+    let (code, stderr) = check(std::slice::from_ref(&covered), false);
+    assert_eq!((code, stderr.as_str()), (Some(0), ""));
+    let (code, stderr) = check(std::slice::from_ref(&covered), true);
+    assert_eq!((code, stderr.as_str()), (Some(0), ""));
+    let (code, stderr) = check(&[covered.clone(), broken.clone()], false);
+    assert_eq!(code, Some(1));
+    assert!(stderr.starts_with("Error: Failed to run apply function on "));
+    let (code, stderr) = check(&[covered.clone(), broken.clone()], true);
+    assert_eq!(code, Some(0));
+    assert!(stderr.starts_with("Warning: Skipped "));
+    assert_eq!(stderr.lines().count(), 1);
+
+    // With misses.
+    let (code, stderr) = check(std::slice::from_ref(&missing), false);
+    assert_eq!((code, stderr.as_str()), (Some(2), ""));
+    let (code, stderr) = check(std::slice::from_ref(&missing), true);
+    assert_eq!((code, stderr.as_str()), (Some(2), ""));
+    let (code, stderr) = check(&[missing.clone(), broken.clone()], false);
+    assert_eq!(code, Some(1));
+    assert!(stderr.starts_with("Error: Failed to run apply function on "));
+    let (code, stderr) = check(&[missing, broken], true);
+    assert_eq!(code, Some(2));
+    assert!(stderr.starts_with("Warning: Skipped "));
+}
+
+#[test]
+fn vcs_root() {
+    let files = [
+        (String::from(".git/HEAD"), String::new()),
+        (
+            String::from("crates/one/src/lib.rs"),
+            String::from("fn one() {}"),
+        ),
+    ];
+    let dir = setup_dir(&files);
+    let path = Path::new(&dir).join("crates").join("one");
+    let expected_stdout = format!(
+        "Missing instrumentation for `one` at {}:1:0.\n",
+        Path::new("crates")
+            .join("one")
+            .join("src")
+            .join("lib.rs")
+            .display()
+    );
+
+    // Detected from `.git`.
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--detect-vcs-root", "--path"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        expected_stdout
+    );
+    assert_eq!(output.stderr, []);
+
+    // Given explicitly.
+    let output = Command::new(BINARY)
+        .args(["--action", "check", "--vcs-root", &dir, "--path"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        expected_stdout
+    );
+    assert_eq!(output.stderr, []);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}